@@ -27,7 +27,7 @@ use alloc::{
 use frame_support::{traits::fungible::Mutate, PalletId};
 use ismp::{
     error::Error as IsmpError,
-    host::StateMachine,
+    host::{IsmpHost, StateMachine},
     module::IsmpModule,
     router::{Post, Request, Response},
 };
@@ -35,6 +35,9 @@ pub use pallet::*;
 use pallet_ismp::primitives::ModuleId;
 use sp_core::H160;
 
+#[cfg(test)]
+mod tests;
+
 /// Constant Pallet ID
 pub const PALLET_ID: ModuleId = ModuleId::Pallet(PalletId(*b"ismp-ast"));
 
@@ -305,6 +308,22 @@ impl<T: Config> Default for IsmpModuleCallback<T> {
 impl<T: Config> IsmpModule for IsmpModuleCallback<T> {
     fn on_accept(&self, request: Post) -> Result<(), IsmpError> {
         let source_chain = request.source;
+        let host = pallet_ismp::host::Host::<T>::default().host_state_machine();
+        if request.dest != host {
+            Err(IsmpError::ImplementationSpecific(format!(
+                "Request is for {:?}, not the host {host:?}",
+                request.dest
+            )))?
+        }
+
+        if request.to != PALLET_ID.to_bytes() {
+            Err(IsmpError::ImplementationSpecific("Request is for an unknown module".to_string()))?
+        }
+
+        // Only requests sent by this pallet on the source chain are accepted
+        if request.from != PALLET_ID.to_bytes() {
+            Err(IsmpError::ImplementationSpecific("Request is from an unknown module".to_string()))?
+        }
 
         match source_chain {
             StateMachine::Ethereum(_) => Pallet::<T>::deposit_event(Event::Request {
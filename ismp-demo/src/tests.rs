@@ -0,0 +1,224 @@
+// Copyright (C) 2023 Polytope Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate as ismp_demo;
+use crate::*;
+use alloc::{boxed::Box, vec, vec::Vec};
+use codec::Encode;
+use frame_support::{
+    assert_ok,
+    traits::{fungible::Inspect, ConstBool, ConstU128, ConstU32, ConstU64, Get},
+};
+use frame_system::EnsureRoot;
+use ismp::{
+    consensus::{ConsensusClient, ConsensusClientId},
+    router::IsmpRouter,
+};
+use pallet_ismp::primitives::{AcceptAll, ConsensusClientProvider};
+use sp_core::H256;
+use sp_runtime::{
+    traits::{BlakeTwo256, IdentityLookup},
+    BuildStorage,
+};
+
+type Block = frame_system::mocking::MockBlock<Test>;
+
+frame_support::construct_runtime!(
+    pub enum Test {
+        System: frame_system::{Pallet, Call, Config<T>, Storage, Event<T>},
+        Timestamp: pallet_timestamp::{Pallet, Call, Storage, Inherent},
+        Balances: pallet_balances::{Pallet, Call, Storage, Config<T>, Event<T>},
+        Ismp: pallet_ismp::{Pallet, Storage, Call, Event<T>, ValidateUnsigned},
+        IsmpDemo: ismp_demo::{Pallet, Call, Event<T>},
+    }
+);
+
+impl frame_system::Config for Test {
+    type BaseCallFilter = frame_support::traits::Everything;
+    type RuntimeOrigin = RuntimeOrigin;
+    type RuntimeCall = RuntimeCall;
+    type Hash = H256;
+    type Hashing = BlakeTwo256;
+    type AccountId = u64;
+    type Lookup = IdentityLookup<Self::AccountId>;
+    type RuntimeEvent = RuntimeEvent;
+    type BlockHashCount = ConstU64<250>;
+    type DbWeight = ();
+    type BlockWeights = ();
+    type BlockLength = ();
+    type Version = ();
+    type Nonce = u64;
+    type Block = Block;
+    type PalletInfo = PalletInfo;
+    type AccountData = pallet_balances::AccountData<u128>;
+    type OnNewAccount = ();
+    type OnKilledAccount = ();
+    type SystemWeightInfo = ();
+    type SS58Prefix = ();
+    type OnSetCode = ();
+    type MaxConsumers = ConstU32<16>;
+}
+
+impl pallet_timestamp::Config for Test {
+    type Moment = u64;
+    type OnTimestampSet = ();
+    type MinimumPeriod = ConstU64<1>;
+    type WeightInfo = ();
+}
+
+impl pallet_balances::Config for Test {
+    type MaxLocks = ();
+    type MaxReserves = ();
+    type ReserveIdentifier = [u8; 8];
+    type Balance = u128;
+    type RuntimeEvent = RuntimeEvent;
+    type DustRemoval = ();
+    type ExistentialDeposit = ConstU128<1>;
+    type AccountStore = System;
+    type WeightInfo = ();
+    type FreezeIdentifier = ();
+    type MaxFreezes = ();
+    type RuntimeHoldReason = ();
+    type MaxHolds = ();
+}
+
+pub struct StateMachineProvider;
+
+impl Get<StateMachine> for StateMachineProvider {
+    fn get() -> StateMachine {
+        StateMachine::Kusama(100)
+    }
+}
+
+pub struct ConsensusProvider;
+
+impl ConsensusClientProvider for ConsensusProvider {
+    fn consensus_client(_id: ConsensusClientId) -> Result<Box<dyn ConsensusClient>, IsmpError> {
+        Err(IsmpError::ImplementationSpecific("No consensus clients".to_string()))
+    }
+}
+
+#[derive(Default)]
+pub struct ModuleRouter;
+
+impl IsmpRouter for ModuleRouter {
+    fn module_for_id(&self, bytes: Vec<u8>) -> Result<Box<dyn IsmpModule>, IsmpError> {
+        if bytes == PALLET_ID.to_bytes() {
+            return Ok(Box::new(IsmpModuleCallback::<Test>::default()))
+        }
+        Err(IsmpError::ImplementationSpecific("Unknown module".to_string()))
+    }
+}
+
+impl pallet_ismp::Config for Test {
+    type RuntimeEvent = RuntimeEvent;
+    const INDEXING_PREFIX: &'static [u8] = b"ISMP";
+    type AdminOrigin = EnsureRoot<u64>;
+    type StateMachine = StateMachineProvider;
+    type TimeProvider = Timestamp;
+    type IsmpRouter = ModuleRouter;
+    type ConsensusClientProvider = ConsensusProvider;
+    type WeightInfo = ();
+    type WeightProvider = ();
+    type MaxPendingConsensusUpdates = ConstU32<5>;
+    type MaxConsensusStatesFinalizedPerBlock = ConstU32<16>;
+    type MaxGetResponseValues = ConstU32<32>;
+    type MaxGetResponseValueSize = ConstU32<64>;
+    type MaxConsensusStateBytes = ConstU32<1024>;
+    type MaxTimeoutBatchSize = ConstU32<8>;
+    type MaxTimestampDrift = ConstU64<{ 60 * 60 }>;
+    type AllowUnsignedHandle = ConstBool<false>;
+    type UnsignedLongevity = ConstU64<25>;
+    type PublishRelayerHints = ConstBool<false>;
+    type RootHistoryDepth = ConstU64<10>;
+    type MaxLeavesPerBlock = ConstU32<4>;
+    type MessageFilter = AcceptAll;
+}
+
+impl Config for Test {
+    type RuntimeEvent = RuntimeEvent;
+    type Balance = u128;
+    type NativeCurrency = Balances;
+    type IsmpDispatcher = pallet_ismp::dispatcher::Dispatcher<Test>;
+}
+
+fn new_test_ext() -> sp_io::TestExternalities {
+    frame_system::GenesisConfig::<Test>::default().build_storage().unwrap().into()
+}
+
+/// A transfer of `amount` to `to` from this pallet on `source` to this pallet on the host
+fn transfer(source: StateMachine, to: u64, amount: u128) -> Post {
+    Post {
+        source,
+        dest: StateMachine::Kusama(100),
+        nonce: 0,
+        from: PALLET_ID.to_bytes(),
+        to: PALLET_ID.to_bytes(),
+        timeout_timestamp: 0,
+        data: Payload { to, from: 1u64, amount }.encode(),
+        gas_limit: 0,
+    }
+}
+
+fn on_accept(post: Post) -> Result<(), IsmpError> {
+    IsmpModuleCallback::<Test>::default().on_accept(post)
+}
+
+#[test]
+fn should_mint_funds_for_transfers_from_this_pallet() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(on_accept(transfer(StateMachine::Kusama(2000), 2, 1_000)));
+        assert_eq!(Balances::balance(&2), 1_000);
+    })
+}
+
+#[test]
+fn should_reject_requests_for_another_host() {
+    new_test_ext().execute_with(|| {
+        let post = Post {
+            dest: StateMachine::Kusama(2001),
+            ..transfer(StateMachine::Kusama(2000), 2, 1_000)
+        };
+        assert!(on_accept(post).is_err());
+        assert_eq!(Balances::balance(&2), 0);
+    })
+}
+
+#[test]
+fn should_reject_requests_for_another_module() {
+    new_test_ext().execute_with(|| {
+        let post = Post { to: vec![1u8; 32], ..transfer(StateMachine::Kusama(2000), 2, 1_000) };
+        assert!(on_accept(post).is_err());
+        assert_eq!(Balances::balance(&2), 0);
+    })
+}
+
+#[test]
+fn should_reject_requests_from_another_module_on_any_source() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let sources = [
+            StateMachine::Kusama(2000),
+            StateMachine::Polkadot(2000),
+            StateMachine::Ethereum(ismp::host::Ethereum::ExecutionLayer),
+        ];
+        for source in sources {
+            let post = Post { from: vec![1u8; 32], ..transfer(source, 2, 1_000) };
+            assert!(on_accept(post).is_err());
+        }
+        assert_eq!(Balances::balance(&2), 0);
+        assert!(System::events().is_empty());
+    })
+}
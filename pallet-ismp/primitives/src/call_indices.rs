@@ -0,0 +1,46 @@
+// Copyright (C) 2023 Polytope Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stable call indices of the pallet-ismp extrinsics, for constructing encoded calls without
+//! depending on the pallet. These must never change across runtime upgrades.
+
+/// Call index of `handle`
+pub const HANDLE: u8 = 0;
+/// Call index of `create_consensus_client`
+pub const CREATE_CONSENSUS_CLIENT: u8 = 1;
+/// Call index of `update_consensus_state`
+pub const UPDATE_CONSENSUS_STATE: u8 = 2;
+/// Call index of `set_config`
+pub const SET_CONFIG: u8 = 3;
+/// Call index of `set_host_state_machine`
+pub const SET_HOST_STATE_MACHINE: u8 = 4;
+/// Call index of `set_consensus_submitters`
+pub const SET_CONSENSUS_SUBMITTERS: u8 = 5;
+/// Call index of `handle_consensus_batch`
+pub const HANDLE_CONSENSUS_BATCH: u8 = 6;
+/// Call index of `handle_unsigned`
+pub const HANDLE_UNSIGNED: u8 = 7;
+/// Call index of `set_paused`
+pub const SET_PAUSED: u8 = 8;
+/// Call index of `pause_state_machine`
+pub const PAUSE_STATE_MACHINE: u8 = 9;
+/// Call index of `pause_consensus_client`
+pub const PAUSE_CONSENSUS_CLIENT: u8 = 10;
+/// Call index of `add_relayer`
+pub const ADD_RELAYER: u8 = 11;
+/// Call index of `remove_relayer`
+pub const REMOVE_RELAYER: u8 = 12;
+/// Call index of `set_consensus_client_name`
+pub const SET_CONSENSUS_CLIENT_NAME: u8 = 13;
@@ -30,13 +30,15 @@ use sp_consensus_aura::{Slot, AURA_ENGINE_ID};
 use sp_core::H256;
 use sp_runtime::{Digest, DigestItem};
 
+#[cfg(feature = "call-indices")]
+pub mod call_indices;
 pub mod mmr;
 
 /// The `ConsensusEngineId` of ISMP digest in the parachain header.
 pub const ISMP_ID: sp_runtime::ConsensusEngineId = *b"ISMP";
 
 /// Queries a request leaf in the mmr
-#[derive(codec::Encode, codec::Decode, scale_info::TypeInfo)]
+#[derive(codec::Encode, codec::Decode, scale_info::TypeInfo, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "std", derive(serde::Deserialize, serde::Serialize))]
 pub struct LeafIndexQuery {
     /// The source of the request
@@ -67,7 +69,7 @@ pub struct SubstrateStateProof {
 }
 
 /// Holds the relevant data needed for request/response proof verification
-#[derive(Debug, Encode, Decode, Clone)]
+#[derive(Debug, Encode, Decode, Clone, PartialEq, Eq)]
 pub struct MembershipProof {
     /// Size of the mmr at the time this proof was generated
     pub mmr_size: u64,
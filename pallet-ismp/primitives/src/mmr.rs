@@ -86,6 +86,14 @@ impl DataOrHash {
             Self::Hash(ref hash) => *hash,
         }
     }
+
+    /// Returns true if both items commit to the same data.
+    ///
+    /// Unlike [PartialEq], this allows a [DataOrHash::Data] to be compared with the
+    /// [DataOrHash::Hash] of its leaf.
+    pub fn equivalent<H: Keccak256>(&self, other: &Self) -> bool {
+        self.hash::<H>() == other.hash::<H>()
+    }
 }
 
 /// Default Merging & Hashing behavior for MMR.
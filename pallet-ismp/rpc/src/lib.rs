@@ -29,16 +29,20 @@ use ismp_primitives::{
     LeafIndexQuery,
 };
 use ismp_rs::{
-    consensus::{ConsensusClientId, StateMachineId},
+    consensus::{ConsensusClientId, StateCommitment, StateMachineHeight, StateMachineId},
     events::{ChallengePeriodStarted, Event, StateMachineUpdated},
     router::{Get, Request, Response},
 };
 use ismp_runtime_api::IsmpRuntimeApi;
+use pallet_ismp::events::DisplayStateMachineId;
 use sc_client_api::{BlockBackend, ProofProvider};
 use serde::{Deserialize, Serialize};
 use sp_api::{ApiExt, ProvideRuntimeApi};
 use sp_blockchain::HeaderBackend;
-use sp_core::offchain::{storage::OffchainDb, OffchainDbExt, OffchainStorage};
+use sp_core::{
+    offchain::{storage::OffchainDb, OffchainDbExt, OffchainStorage},
+    H256,
+};
 use sp_runtime::traits::Block as BlockT;
 use std::{collections::HashMap, fmt::Display, sync::Arc};
 
@@ -62,7 +66,7 @@ impl<Hash: std::fmt::Debug> Display for BlockNumberOrHash<Hash> {
 }
 
 /// Contains a scale encoded Mmr Proof or Trie proof
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct Proof {
     /// Scale encoded `pallet_ismp::primitives::Proof` or state trie proof `Vec<Vec<u8>>`
     pub proof: Vec<u8>,
@@ -72,6 +76,58 @@ pub struct Proof {
     pub height: u32,
 }
 
+/// An ISMP event alongside human readable names of the state machines it refers to
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FormattedEvent {
+    /// The ISMP event
+    pub event: Event,
+    /// State machines referenced by the event, formatted like `StateMachine::Polkadot(1000)/PARA`
+    pub state_machines: Vec<String>,
+}
+
+impl From<Event> for FormattedEvent {
+    fn from(event: Event) -> Self {
+        let state_machines = match &event {
+            Event::StateMachineUpdated(updated) => {
+                vec![DisplayStateMachineId(&updated.state_machine_id).to_string()]
+            }
+            Event::ChallengePeriodStarted(started) => started
+                .state_machines
+                .iter()
+                .map(|height| DisplayStateMachineId(&height.id).to_string())
+                .collect(),
+            _ => vec![],
+        };
+        FormattedEvent { event, state_machines }
+    }
+}
+
+/// Default maximum number of blocks events can be queried for in a single call
+pub const DEFAULT_MAX_EVENT_BLOCKS: usize = 100;
+
+/// Ensure an events query is for at most `max` blocks, and that a query delimited by block
+/// numbers doesn't start after it ends.
+fn validate_event_blocks<Hash>(blocks: &[BlockNumberOrHash<Hash>], max: usize) -> Result<()> {
+    if blocks.len() > max {
+        Err(runtime_error_into_rpc_error(format!(
+            "Events can be queried for at most {max} blocks, {} were requested",
+            blocks.len()
+        )))?
+    }
+
+    if let (Some(BlockNumberOrHash::Number(start)), Some(BlockNumberOrHash::Number(end))) =
+        (blocks.first(), blocks.last())
+    {
+        if start > end {
+            Err(runtime_error_into_rpc_error(format!(
+                "Start block {start} is after end block {end}"
+            )))?
+        }
+    }
+
+    Ok(())
+}
+
 /// Converts a runtime trap into an RPC error.
 fn runtime_error_into_rpc_error(e: impl std::fmt::Display) -> RpcError {
     RpcError::Call(CallError::Custom(ErrorObject::owned(
@@ -95,6 +151,14 @@ where
     #[method(name = "ismp_queryResponses")]
     fn query_responses(&self, query: Vec<LeafIndexQuery>) -> Result<Vec<Response>>;
 
+    /// Query the full request with the given commitment
+    #[method(name = "ismp_queryRequestByCommitment")]
+    fn query_request_by_commitment(&self, commitment: H256) -> Result<Request>;
+
+    /// Query the full response with the given commitment
+    #[method(name = "ismp_queryResponseByCommitment")]
+    fn query_response_by_commitment(&self, commitment: H256) -> Result<Response>;
+
     /// Query mmr proof for some requests
     #[method(name = "ismp_queryRequestsMmrProof")]
     fn query_requests_mmr_proof(&self, height: u32, query: Vec<LeafIndexQuery>) -> Result<Proof>;
@@ -115,6 +179,28 @@ where
         client_id: ConsensusClientId,
     ) -> Result<Vec<u8>>;
 
+    /// Query the scale encoded consensus states of several clients at the given height or the
+    /// best block, in the same order as the ids
+    #[method(name = "ismp_batchQueryConsensusStates")]
+    fn batch_query_consensus_states(
+        &self,
+        height: Option<u32>,
+        client_ids: Vec<ConsensusClientId>,
+    ) -> Result<Vec<Option<Vec<u8>>>>;
+
+    /// Query the state commitments at several state machine heights at the given height or the
+    /// best block, in the same order as the heights
+    #[method(name = "ismp_batchQueryStateCommitments")]
+    fn batch_query_state_commitments(
+        &self,
+        at: Option<u32>,
+        heights: Vec<StateMachineHeight>,
+    ) -> Result<Vec<Option<StateCommitment>>>;
+
+    /// Query the human readable name of a consensus client
+    #[method(name = "ismp_queryConsensusClientName")]
+    fn query_consensus_client_name(&self, client_id: ConsensusClientId) -> Result<Option<String>>;
+
     /// Query timestamp of when this client was last updated in seconds
     #[method(name = "ismp_queryConsensusUpdateTime")]
     fn query_consensus_update_time(&self, client_id: ConsensusClientId) -> Result<u64>;
@@ -131,6 +217,14 @@ where
     #[method(name = "ismp_queryLatestMessagingHeight")]
     fn query_latest_messaging_height(&self, id: StateMachineId) -> Result<u64>;
 
+    /// Query the ids of all frozen consensus clients
+    #[method(name = "ismp_queryFrozenClients")]
+    fn query_frozen_clients(&self) -> Result<Vec<ConsensusClientId>>;
+
+    /// Query all frozen state machines
+    #[method(name = "ismp_queryFrozenStateMachines")]
+    fn query_frozen_state_machines(&self) -> Result<Vec<StateMachineId>>;
+
     /// Query ISMP Events that were deposited in a series of blocks
     /// Using String keys because HashMap fails to deserialize when key is not a String
     #[method(name = "ismp_queryEvents")]
@@ -139,6 +233,14 @@ where
         block_numbers: Vec<BlockNumberOrHash<Hash>>,
     ) -> Result<HashMap<String, Vec<Event>>>;
 
+    /// Query ISMP Events like `ismp_queryEvents`, with the state machines they reference
+    /// formatted for display
+    #[method(name = "ismp_queryFormattedEvents")]
+    fn query_formatted_events(
+        &self,
+        block_numbers: Vec<BlockNumberOrHash<Hash>>,
+    ) -> Result<HashMap<String, Vec<FormattedEvent>>>;
+
     /// Query pending get requests that have a `state_machine_height` <=  `height`.
     #[method(name = "ismp_pendingGetRequests")]
     fn pending_get_requests(&self, height: u64) -> Result<Vec<Get>>;
@@ -148,13 +250,25 @@ where
 pub struct IsmpRpcHandler<C, B, S> {
     client: Arc<C>,
     offchain_db: OffchainDb<S>,
+    max_event_blocks: usize,
     _marker: std::marker::PhantomData<B>,
 }
 
 impl<C, B, S> IsmpRpcHandler<C, B, S> {
     /// Create new `IsmpRpcHandler` with the given reference to the client.
     pub fn new(client: Arc<C>, offchain_storage: S) -> Self {
-        Self { client, offchain_db: OffchainDb::new(offchain_storage), _marker: Default::default() }
+        Self {
+            client,
+            offchain_db: OffchainDb::new(offchain_storage),
+            max_event_blocks: DEFAULT_MAX_EVENT_BLOCKS,
+            _marker: Default::default(),
+        }
+    }
+
+    /// Set the maximum number of blocks events can be queried for in a single call
+    pub fn with_max_event_blocks(mut self, max_event_blocks: usize) -> Self {
+        self.max_event_blocks = max_event_blocks;
+        self
     }
 }
 
@@ -199,6 +313,26 @@ where
             .map_err(|_| runtime_error_into_rpc_error("Error fetching responses"))
     }
 
+    fn query_request_by_commitment(&self, commitment: H256) -> Result<Request> {
+        let mut api = self.client.runtime_api();
+        api.register_extension(OffchainDbExt::new(self.offchain_db.clone()));
+        let at = self.client.info().best_hash;
+        api.request_by_commitment(at, commitment)
+            .ok()
+            .flatten()
+            .ok_or_else(|| runtime_error_into_rpc_error("Error fetching request"))
+    }
+
+    fn query_response_by_commitment(&self, commitment: H256) -> Result<Response> {
+        let mut api = self.client.runtime_api();
+        api.register_extension(OffchainDbExt::new(self.offchain_db.clone()));
+        let at = self.client.info().best_hash;
+        api.response_by_commitment(at, commitment)
+            .ok()
+            .flatten()
+            .ok_or_else(|| runtime_error_into_rpc_error("Error fetching response"))
+    }
+
     fn query_requests_mmr_proof(&self, height: u32, query: Vec<LeafIndexQuery>) -> Result<Proof> {
         let mut api = self.client.runtime_api();
         api.register_extension(OffchainDbExt::new(self.offchain_db.clone()));
@@ -266,6 +400,41 @@ where
             .ok_or_else(|| runtime_error_into_rpc_error("Error fetching Consensus state"))
     }
 
+    fn batch_query_consensus_states(
+        &self,
+        height: Option<u32>,
+        client_ids: Vec<ConsensusClientId>,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        let api = self.client.runtime_api();
+        let at = height
+            .and_then(|height| self.client.block_hash(height.into()).ok().flatten())
+            .unwrap_or(self.client.info().best_hash);
+        api.batch_consensus_states(at, client_ids)
+            .map_err(|_| runtime_error_into_rpc_error("Error fetching Consensus states"))
+    }
+
+    fn batch_query_state_commitments(
+        &self,
+        at: Option<u32>,
+        heights: Vec<StateMachineHeight>,
+    ) -> Result<Vec<Option<StateCommitment>>> {
+        let api = self.client.runtime_api();
+        let at = at
+            .and_then(|height| self.client.block_hash(height.into()).ok().flatten())
+            .unwrap_or(self.client.info().best_hash);
+        api.batch_state_commitments(at, heights)
+            .map_err(|_| runtime_error_into_rpc_error("Error fetching state commitments"))
+    }
+
+    fn query_consensus_client_name(&self, client_id: ConsensusClientId) -> Result<Option<String>> {
+        let api = self.client.runtime_api();
+        let at = self.client.info().best_hash;
+        let name = api
+            .consensus_client_name(at, client_id)
+            .map_err(|_| runtime_error_into_rpc_error("Error fetching consensus client name"))?;
+        Ok(name.map(|name| String::from_utf8_lossy(&name).into_owned()))
+    }
+
     fn query_consensus_update_time(&self, client_id: ConsensusClientId) -> Result<u64> {
         let api = self.client.runtime_api();
         let at = self.client.info().best_hash;
@@ -292,6 +461,20 @@ where
         })
     }
 
+    fn query_frozen_clients(&self) -> Result<Vec<ConsensusClientId>> {
+        let api = self.client.runtime_api();
+        let at = self.client.info().best_hash;
+        api.frozen_consensus_clients(at)
+            .map_err(|_| runtime_error_into_rpc_error("Error fetching frozen consensus clients"))
+    }
+
+    fn query_frozen_state_machines(&self) -> Result<Vec<StateMachineId>> {
+        let api = self.client.runtime_api();
+        let at = self.client.info().best_hash;
+        api.frozen_state_machines(at)
+            .map_err(|_| runtime_error_into_rpc_error("Error fetching frozen state machines"))
+    }
+
     fn pending_get_requests(&self, height: u64) -> Result<Vec<Get>> {
         let mut api = self.client.runtime_api();
         api.register_extension(OffchainDbExt::new(self.offchain_db.clone()));
@@ -306,6 +489,7 @@ where
         &self,
         block_numbers: Vec<BlockNumberOrHash<Block::Hash>>,
     ) -> Result<HashMap<String, Vec<Event>>> {
+        validate_event_blocks(&block_numbers, self.max_event_blocks)?;
         let mut events = HashMap::new();
         for block_number_or_hash in block_numbers {
             let mut api = self.client.runtime_api();
@@ -395,6 +579,17 @@ where
         Ok(events)
     }
 
+    fn query_formatted_events(
+        &self,
+        block_numbers: Vec<BlockNumberOrHash<Block::Hash>>,
+    ) -> Result<HashMap<String, Vec<FormattedEvent>>> {
+        let events = self.query_events(block_numbers)?;
+        Ok(events
+            .into_iter()
+            .map(|(block, events)| (block, events.into_iter().map(Into::into).collect()))
+            .collect())
+    }
+
     fn query_latest_messaging_height(&self, id: StateMachineId) -> Result<u64> {
         let api = self.client.runtime_api();
         let at = self.client.info().best_hash;
@@ -403,3 +598,26 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_validate_event_blocks() {
+        let numbers = |range: std::ops::RangeInclusive<u32>| {
+            range.map(BlockNumberOrHash::<H256>::Number).collect::<Vec<_>>()
+        };
+
+        assert!(validate_event_blocks(&numbers(1..=10), 10).is_ok());
+        assert!(validate_event_blocks(&numbers(1..=11), 10).is_err());
+
+        let mut reversed = numbers(1..=10);
+        reversed.reverse();
+        assert!(validate_event_blocks(&reversed, 10).is_err());
+
+        let hashes =
+            vec![BlockNumberOrHash::Hash(H256::repeat_byte(2)), BlockNumberOrHash::Number(1)];
+        assert!(validate_event_blocks(&hashes, 10).is_ok());
+    }
+}
@@ -19,10 +19,14 @@
 #![deny(missing_docs)]
 
 use ismp_rs::{
-    consensus::{ConsensusClientId, StateMachineId},
+    consensus::{ConsensusClientId, StateCommitment, StateMachineHeight, StateMachineId},
+    host::StateMachine,
+    messaging::Message,
     router::{Get, Request, Response},
 };
-use pallet_ismp::primitives::{Error, Proof};
+use pallet_ismp::primitives::{BlockLeaves, Error, Proof};
+use sp_core::H256;
+use sp_runtime::traits::NumberFor;
 
 use ismp_primitives::{
     mmr::{Leaf, LeafIndex},
@@ -40,21 +44,47 @@ sp_api::decl_runtime_apis! {
         /// Return the on-chain MMR root hash.
         fn mmr_root() -> Result<Hash, Error>;
 
+        /// Return the MMR root hash at the end of the given block, if it's still retained
+        fn mmr_root_at(block_number: NumberFor<Block>) -> Option<H256>;
+
+        /// Return the positions in the MMR of the leaves pushed in the given block, if it's still
+        /// retained, flagged as truncated if the block pushed more leaves than could be indexed
+        fn leaves_at_block(block_number: NumberFor<Block>) -> BlockLeaves;
+
         /// Generate a proof for the provided leaf indices
         fn generate_proof(
             leaf_indices: Vec<LeafIndex>
         ) -> Result<(Vec<Leaf>, Proof<Hash>), Error>;
 
+        /// Generate a proof for the outgoing requests to `dest_chain` with nonces in
+        /// `start_nonce..=end_nonce`
+        fn generate_proof_for_range(
+            start_nonce: u64,
+            end_nonce: u64,
+            dest_chain: StateMachine,
+        ) -> Result<(Vec<Leaf>, Proof<Hash>), Error>;
+
         /// Fetch all ISMP events
         fn block_events() -> Vec<pallet_ismp::events::Event>;
 
         /// Return the scale encoded consensus state
         fn consensus_state(id: ConsensusClientId) -> Option<Vec<u8>>;
 
+        /// Return the scale encoded consensus states of the given clients, in the same order
+        fn batch_consensus_states(ids: Vec<ConsensusClientId>) -> Vec<Option<Vec<u8>>>;
+
+        /// Return the state commitments at the given heights, in the same order
+        fn batch_state_commitments(
+            heights: Vec<StateMachineHeight>
+        ) -> Vec<Option<StateCommitment>>;
+
+        /// Return the human readable name of a consensus client
+        fn consensus_client_name(id: ConsensusClientId) -> Option<Vec<u8>>;
+
         /// Return the timestamp this client was last updated in seconds
         fn consensus_update_time(id: ConsensusClientId) -> Option<u64>;
 
-        /// Return the challenge period timestamp
+        /// Return the challenge period of a consensus client in seconds
         fn challenge_period(id: ConsensusClientId) -> Option<u64>;
 
         /// Return the latest height of the state machine
@@ -63,6 +93,12 @@ sp_api::decl_runtime_apis! {
         /// Return the most recent height we've processed requests for a state machine
         fn latest_messaging_height(id: StateMachineId) -> Option<u64>;
 
+        /// Return the ids of all frozen consensus clients
+        fn frozen_consensus_clients() -> Vec<ConsensusClientId>;
+
+        /// Return all frozen state machines
+        fn frozen_state_machines() -> Vec<StateMachineId>;
+
         /// Get Request Leaf Indices
         fn get_request_leaf_indices(leaf_queries: Vec<LeafIndexQuery>) -> Vec<LeafIndex>;
 
@@ -72,6 +108,22 @@ sp_api::decl_runtime_apis! {
         /// Get actual requests
         fn get_requests(leaf_indices: Vec<LeafIndex>) -> Vec<Request>;
 
+        /// Get the request with the given commitment
+        fn request_by_commitment(commitment: H256) -> Option<Request>;
+
+        /// Get the response with the given commitment
+        fn response_by_commitment(commitment: H256) -> Option<Response>;
+
+        /// Return the block in which the outgoing request with the given nonce was dispatched
+        fn request_block(nonce: u64) -> Option<NumberFor<Block>>;
+
+        /// Fetch the outgoing requests to `dest` that haven't been timed out
+        fn pending_requests_for_dest(dest: StateMachine) -> Vec<Request>;
+
+        /// Simulate handling the messages like a `handle` call without persisting any changes,
+        /// returning whether each message would be handled successfully
+        fn simulate_messages(messages: Vec<Message>) -> Vec<bool>;
+
         /// Fetch all Get requests that have received no response
         fn pending_get_requests() -> Vec<Get>;
 
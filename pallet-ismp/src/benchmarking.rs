@@ -34,14 +34,17 @@ T: pallet_timestamp::Config,
 pub mod benchmarks {
     use super::*;
     use crate::{
-        dispatcher::Dispatcher,
+        dispatcher::{Dispatcher, Receipt},
         host::Host,
         mocks::ismp::{setup_mock_client, MOCK_CONSENSUS_STATE_ID, MODULE_ID},
+        primitives::RequestMetadata,
         Config, Event, Pallet, RequestCommitments, RequestReceipts, ResponseReceipts,
     };
+    use alloc::collections::BTreeMap;
+    use core::cell::RefCell;
     use frame_support::traits::{Get, Hooks};
     use frame_system::EventRecord;
-    use ismp_primitives::{mmr::Leaf, LeafIndexQuery};
+    use ismp_primitives::mmr::{DataOrHash, Leaf, MmrHasher, NodeIndex};
     use ismp_rs::{
         consensus::{StateCommitment, StateMachineId},
         host::{Ethereum, StateMachine},
@@ -92,7 +95,17 @@ pub mod benchmarks {
         _(RawOrigin::Root, message);
 
         assert_last_event::<T>(
-            Event::ConsensusClientCreated { consensus_client_id: MOCK_CONSENSUS_STATE_ID }.into(),
+            Event::ConsensusClientCreated {
+                consensus_client_id: MOCK_CONSENSUS_STATE_ID,
+                seeded_heights: vec![StateMachineHeight {
+                    id: StateMachineId {
+                        state_id: StateMachine::Ethereum(Ethereum::ExecutionLayer),
+                        consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+                    },
+                    height: 1,
+                }],
+            }
+            .into(),
         );
     }
 
@@ -142,10 +155,7 @@ pub mod benchmarks {
         let request = Request::Post(post.clone());
 
         let commitment = hash_request::<Host<T>>(&request);
-        RequestCommitments::<T>::insert(
-            commitment,
-            LeafIndexQuery { source_chain: post.source, dest_chain: post.dest, nonce: post.nonce },
-        );
+        RequestCommitments::<T>::insert(commitment, RequestMetadata::from(&request));
 
         let response = Response::Post(PostResponse { post, response: vec![] });
         let request_commitment = hash_request::<Host<T>>(&response.request());
@@ -180,10 +190,7 @@ pub mod benchmarks {
         let request = Request::Post(post.clone());
 
         let commitment = hash_request::<Host<T>>(&request);
-        RequestCommitments::<T>::insert(
-            commitment,
-            LeafIndexQuery { source_chain: post.source, dest_chain: post.dest, nonce: post.nonce },
-        );
+        RequestCommitments::<T>::insert(commitment, RequestMetadata::from(&request));
 
         let msg = TimeoutMessage::Post {
             requests: vec![request],
@@ -197,6 +204,75 @@ pub mod benchmarks {
         assert!(RequestCommitments::<T>::get(commitment).is_none());
     }
 
+    // Measures repeated reads of a state commitment at the same height within a single host,
+    // as happens when a batch of messages is proven against the same height.
+    #[benchmark]
+    fn read_state_machine_commitment(x: Linear<1, 100>) {
+        let host = Host::<T>::default();
+        let height = setup_mock_client::<_, T>(&host);
+
+        #[block]
+        {
+            for _ in 0..x {
+                host.state_machine_commitment(height.clone()).unwrap();
+            }
+        }
+    }
+
+    /// An in-memory mmr store, so proofs can be generated without touching the pallet's mmr
+    #[derive(Default)]
+    struct MemStore(RefCell<BTreeMap<NodeIndex, DataOrHash>>);
+
+    impl mmr_lib::MMRStore<DataOrHash> for &MemStore {
+        fn get_elem(&self, pos: NodeIndex) -> mmr_lib::Result<Option<DataOrHash>> {
+            Ok(self.0.borrow().get(&pos).cloned())
+        }
+
+        fn append(&mut self, pos: NodeIndex, elems: Vec<DataOrHash>) -> mmr_lib::Result<()> {
+            let mut store = self.0.borrow_mut();
+            for (i, elem) in elems.into_iter().enumerate() {
+                store.insert(pos + i as NodeIndex, elem);
+            }
+            Ok(())
+        }
+    }
+
+    // Measures verifying an mmr membership proof of `n` leaves in an mmr of `2^h` leaves, the
+    // proofs counterparties produce for requests and responses committed to their mmr
+    #[benchmark]
+    fn verify_membership(n: Linear<1, 100>, h: Linear<7, 16>) {
+        let store = MemStore::default();
+        let mut mmr = mmr_lib::MMR::<_, MmrHasher<Host<T>>, _>::new(0, &store);
+        let count = 1u64 << h;
+        // prove leaves spread across the mmr
+        let step = count / n as u64;
+        let mut leaves = vec![];
+        for nonce in 0..count {
+            let leaf = DataOrHash::Data(Leaf::Request(Request::Post(Post {
+                source: StateMachine::Ethereum(Ethereum::ExecutionLayer),
+                dest: <T as Config>::StateMachine::get(),
+                nonce,
+                from: MODULE_ID.to_bytes(),
+                to: MODULE_ID.to_bytes(),
+                timeout_timestamp: 5000,
+                data: vec![2u8; 64],
+                gas_limit: 0,
+            })));
+            let pos = mmr.push(leaf.clone()).unwrap();
+            if nonce % step == 0 && leaves.len() < n as usize {
+                leaves.push((pos, leaf));
+            }
+        }
+        mmr.commit().unwrap();
+        let root = mmr.get_root().unwrap();
+        let proof = mmr.gen_proof(leaves.iter().map(|(pos, _)| *pos).collect()).unwrap();
+
+        #[block]
+        {
+            assert!(proof.verify(root, leaves).unwrap());
+        }
+    }
+
     #[benchmark]
     fn on_finalize(x: Linear<1, 100>) {
         for nonce in 0..x {
@@ -272,10 +348,7 @@ pub mod benchmarks {
             gas_limit: 0,
         };
         let request_commitment = hash_request::<Host<T>>(&Request::Post(post.clone()));
-        RequestCommitments::<T>::insert(
-            request_commitment,
-            LeafIndexQuery { source_chain: post.source, dest_chain: post.dest, nonce: 0 },
-        );
+        RequestReceipts::<T>::insert(request_commitment, Receipt::Ok);
 
         let response = PostResponse { post, response: vec![1u8; 64] };
 
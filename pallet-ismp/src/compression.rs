@@ -0,0 +1,42 @@
+// Copyright (C) 2023 Polytope Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compression of request data.
+//! Post requests dispatched with compressed data are wrapped in a
+//! [`crate::primitives::PostEnvelope`] that flags the data as compressed, it's decompressed before
+//! it's passed to the destination module.
+
+use alloc::{format, vec::Vec};
+use ismp_rs::error::Error as IsmpError;
+
+/// Maximum size of decompressed request data
+pub const MAX_DECOMPRESSED_DATA_SIZE: usize = 4 * 1024 * 1024;
+
+/// Compress request data, the output is what gets committed to the mmr
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec(data, 6)
+}
+
+/// Decompress request data produced by [`compress`]
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, IsmpError> {
+    miniz_oxide::inflate::decompress_to_vec_with_limit(data, MAX_DECOMPRESSED_DATA_SIZE).map_err(
+        |err| {
+            IsmpError::ImplementationSpecific(format!(
+                "Failed to decompress request data: {:?}",
+                err.status
+            ))
+        },
+    )
+}
@@ -14,14 +14,31 @@
 // limitations under the License.
 
 //! Implementation for the ISMP Router
-use crate::{host::Host, Config, Pallet};
-use codec::{Decode, Encode};
+use crate::{
+    host::Host,
+    primitives::{
+        AckCode, DispatchErrorKind, DispatchFailure, ModuleId, PostEnvelope,
+        CANCELLATION_MODULE_ID, ENVELOPE_MODULE_ID,
+    },
+    weight_info::{IsmpModuleWeight, WeightProvider},
+    CancelledRequests, Config, Event, ModuleGasUsed, Pallet, RequestCommitments, RequestReceipts,
+    WeightConsumed,
+};
+use alloc::{boxed::Box, collections::BTreeMap, format, string::ToString, vec::Vec};
+use codec::{Decode, DecodeAll, Encode};
 use core::marker::PhantomData;
+use frame_support::{traits::Get as _, weights::Weight};
 use ismp_rs::{
     error::Error as IsmpError,
-    host::IsmpHost,
-    router::{DispatchRequest, Get, IsmpDispatcher, Post, PostResponse, Request, Response},
+    host::{IsmpHost, StateMachine},
+    module::IsmpModule,
+    router::{
+        DispatchGet, DispatchPost, DispatchRequest, Get, GetResponse, IsmpDispatcher, IsmpRouter,
+        Post, PostResponse, Request, Response,
+    },
+    util::hash_request,
 };
+use sp_runtime::SaturatedConversion;
 
 /// A receipt or an outgoing or incoming request or response
 #[derive(Encode, Decode, scale_info::TypeInfo)]
@@ -30,6 +47,23 @@ pub enum Receipt {
     Ok,
 }
 
+/// A GET request for the same keys at several heights of the destination state machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DispatchGetMulti {
+    /// Destination state machine
+    pub dest: StateMachine,
+    /// Module Id of the sender
+    pub from: Vec<u8>,
+    /// Storage keys to read
+    pub keys: Vec<Vec<u8>>,
+    /// Heights of the destination state machine to read the keys at
+    pub heights: Vec<u64>,
+    /// Host timestamp at which the requests time out
+    pub timeout_timestamp: u64,
+    /// Gas limit for executing the response
+    pub gas_limit: u64,
+}
+
 /// The dispatcher commits outgoing requests and responses to the mmr
 pub struct Dispatcher<T>(PhantomData<T>);
 
@@ -39,12 +73,167 @@ impl<T> Default for Dispatcher<T> {
     }
 }
 
-impl<T> IsmpDispatcher for Dispatcher<T>
-where
-    T: Config,
-{
-    fn dispatch_request(&self, request: DispatchRequest) -> Result<(), IsmpError> {
+impl<T: Config> Dispatcher<T> {
+    /// Cancel an outgoing post request that hasn't been delivered. The sending module's
+    /// `on_timeout` is called once the destination acknowledges the cancellation, which it only
+    /// does if it hasn't received the request. Like the `from` of dispatched requests, modules are
+    /// trusted to only cancel their own requests.
+    pub fn cancel_request(&self, post: Post) -> Result<(), DispatchFailure> {
+        Pallet::<T>::cancel_request(post)
+    }
+
+    /// Dispatch a GET request for every height in the request. The sender receives one response
+    /// per height, the height a response's values were read at is the height of its request.
+    pub fn dispatch_get_multi(&self, request: DispatchGetMulti) -> Result<(), IsmpError> {
+        let mut heights = request.heights;
+        heights.sort_unstable();
+        heights.dedup();
+        if heights.is_empty() {
+            Err(IsmpError::ImplementationSpecific("No heights to read from".to_string()))?
+        }
+
+        for height in heights {
+            self.dispatch_request(DispatchRequest::Get(DispatchGet {
+                dest: request.dest,
+                from: request.from.clone(),
+                keys: request.keys.clone(),
+                height,
+                timeout_timestamp: request.timeout_timestamp,
+                gas_limit: request.gas_limit,
+            }))?;
+        }
+
+        Ok(())
+    }
+
+    /// Dispatch a GET request for keys of the host's own state. The request isn't relayed, it's
+    /// answered immediately with the values at the current block, which is the height of the
+    /// request passed to the sending module's `on_response`. The response is bounded like
+    /// responses from other state machines.
+    pub fn dispatch_self_get(&self, request: DispatchGet) -> Result<(), DispatchFailure> {
+        Self::ensure_unreserved(&[&request.from[..]])?;
+        let host = Host::<T>::default();
+        if request.dest != host.host_state_machine() {
+            Err(DispatchFailure::new(
+                DispatchErrorKind::DestinationNotHost,
+                format!(
+                    "Self GET requests must be addressed to the host state machine, not {:?}",
+                    request.dest
+                ),
+            ))?
+        }
+        if request.keys.len() > T::MaxGetResponseValues::get() as usize {
+            Err(DispatchFailure::new(
+                DispatchErrorKind::ResponseTooLarge,
+                format!(
+                    "Self GET requests can read at most {} keys",
+                    T::MaxGetResponseValues::get()
+                ),
+            ))?
+        }
+
+        let values = request
+            .keys
+            .iter()
+            .map(|key| (key.clone(), sp_io::storage::get(key).map(|value| value.to_vec())))
+            .collect::<BTreeMap<_, _>>();
+        let max_size = T::MaxGetResponseValueSize::get() as usize;
+        if values.values().flatten().any(|value| value.len() > max_size) {
+            Err(DispatchFailure::new(
+                DispatchErrorKind::ResponseTooLarge,
+                format!("Self GET response values can be at most {max_size} bytes"),
+            ))?
+        }
+
+        let get = Get {
+            source: host.host_state_machine(),
+            dest: host.host_state_machine(),
+            nonce: host.next_nonce(),
+            from: request.from,
+            keys: request.keys,
+            height: frame_system::Pallet::<T>::block_number().saturated_into(),
+            timeout_timestamp: request.timeout_timestamp,
+            gas_limit: request.gas_limit,
+        };
+        ValidatingRouter::<T>::default()
+            .module_for_id(get.from.clone())
+            .and_then(|module| module.on_response(Response::Get(GetResponse { get, values })))
+            .map_err(|err| {
+                DispatchFailure::new(DispatchErrorKind::ModuleCallbackFailed, format!("{err:?}"))
+            })
+    }
+
+    /// Dispatch a post request asking the destination to respond with an [`AckCode`] reporting
+    /// whether its module accepted the request. The request is delivered through
+    /// [`ENVELOPE_MODULE_ID`] and the acknowledgement is the only response it can receive.
+    pub fn dispatch_post_with_ack(&self, post: DispatchPost) -> Result<(), DispatchFailure> {
+        Self::ensure_unreserved(&[&post.from[..], &post.to[..]])?;
+        self.dispatch_unchecked(DispatchRequest::Post(PostEnvelope::seal(post, true, false)))
+    }
+
+    /// Dispatch a post request with its data compressed. The request is committed to with the
+    /// compressed data, which is decompressed before it's passed to the destination module.
+    #[cfg(feature = "compression")]
+    pub fn dispatch_compressed_post(&self, mut post: DispatchPost) -> Result<(), DispatchFailure> {
+        Self::ensure_unreserved(&[&post.from[..], &post.to[..]])?;
+        post.data = crate::compression::compress(&post.data);
+        self.dispatch_unchecked(DispatchRequest::Post(PostEnvelope::seal(post, false, true)))
+    }
+}
+
+impl<T: Config> Dispatcher<T> {
+    /// Dispatch a request, returning the kind of failure if it can't be dispatched. Requests can't
+    /// be sent from or addressed to the module ids reserved by the pallet.
+    pub fn try_dispatch_request(&self, request: DispatchRequest) -> Result<(), DispatchFailure> {
+        match request {
+            DispatchRequest::Get(ref get) => Self::ensure_unreserved(&[&get.from[..]])?,
+            DispatchRequest::Post(ref post) => {
+                Self::ensure_unreserved(&[&post.from[..], &post.to[..]])?
+            }
+        }
+
+        self.dispatch_unchecked(request)
+    }
+
+    /// Reject module ids reserved by the pallet, requests from or to these modules are only
+    /// dispatched by the pallet itself
+    fn ensure_unreserved(ids: &[&[u8]]) -> Result<(), DispatchFailure> {
+        let reserved = [ENVELOPE_MODULE_ID.0, CANCELLATION_MODULE_ID.0];
+        if let Some(id) =
+            ids.iter().find(|id| reserved.iter().any(|reserved| reserved.as_slice() == **id))
+        {
+            Err(DispatchFailure::new(
+                DispatchErrorKind::ReservedModule,
+                format!("Module id {id:?} is reserved by the pallet"),
+            ))?
+        }
+
+        Ok(())
+    }
+
+    /// Dispatch a request without checking its module ids, the pallet's own modules send their
+    /// requests through this
+    pub(crate) fn dispatch_unchecked(
+        &self,
+        request: DispatchRequest,
+    ) -> Result<(), DispatchFailure> {
         let host = Host::<T>::default();
+        let dest = match request {
+            DispatchRequest::Get(ref dispatch_get) => dispatch_get.dest,
+            DispatchRequest::Post(ref dispatch_post) => dispatch_post.dest,
+        };
+        // Requests addressed to the host would only ever be routed back to it, the host's own
+        // state is read with `dispatch_self_get`
+        if dest == host.host_state_machine() {
+            Err(DispatchFailure::new(
+                DispatchErrorKind::DestinationIsHost,
+                format!(
+                    "Cannot dispatch request to the host state machine {dest:?}, use \
+                     `Dispatcher::dispatch_self_get` to read the host's own state"
+                ),
+            ))?
+        }
+
         let request = match request {
             DispatchRequest::Get(dispatch_get) => {
                 let get = Get {
@@ -74,7 +263,16 @@ where
             }
         };
 
-        Pallet::<T>::dispatch_request(request)?;
+        Pallet::<T>::dispatch_request(request)
+    }
+}
+
+impl<T> IsmpDispatcher for Dispatcher<T>
+where
+    T: Config,
+{
+    fn dispatch_request(&self, request: DispatchRequest) -> Result<(), IsmpError> {
+        self.try_dispatch_request(request)?;
 
         Ok(())
     }
@@ -87,3 +285,230 @@ where
         Ok(())
     }
 }
+
+/// Wraps the configured [`Config::IsmpRouter`], validating messages before they're dispatched to
+/// modules
+pub struct ValidatingRouter<T>(PhantomData<T>);
+
+impl<T> Default for ValidatingRouter<T> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T: Config> IsmpRouter for ValidatingRouter<T> {
+    fn module_for_id(&self, bytes: Vec<u8>) -> Result<Box<dyn IsmpModule>, IsmpError> {
+        // The envelope module delivers to modules returned by this router, which are metered
+        if bytes == ENVELOPE_MODULE_ID.0.to_vec() {
+            return Ok(Box::new(EnvelopeModule::<T>(PhantomData)))
+        }
+
+        let module: Box<dyn IsmpModule> = if bytes == CANCELLATION_MODULE_ID.0.to_vec() {
+            Box::new(CancellationModule::<T>(PhantomData))
+        } else {
+            T::IsmpRouter::default().module_for_id(bytes.clone())?
+        };
+        Ok(Box::new(ValidatingModule::<T> { module, id: bytes, _phantom: PhantomData }))
+    }
+}
+
+/// A module returned by the [`ValidatingRouter`]
+struct ValidatingModule<T> {
+    module: Box<dyn IsmpModule>,
+    /// Id the module was routed by
+    id: Vec<u8>,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Config> ValidatingModule<T> {
+    /// Returns the weight the weight provider declares for one of the module's callbacks
+    fn declared_weight(&self, weight: impl FnOnce(&dyn IsmpModuleWeight) -> Weight) -> Weight {
+        ModuleId::from_bytes(&self.id)
+            .ok()
+            .and_then(T::WeightProvider::module_callback)
+            .map(|provider| weight(&*provider))
+            .unwrap_or_default()
+    }
+
+    /// Ensure the values proven for a get request are within `T::MaxGetResponseValues` and
+    /// `T::MaxGetResponseValueSize`, modules aren't called with responses that exceed them
+    fn check_get_response(response: &GetResponse) -> Result<(), IsmpError> {
+        let max_values = T::MaxGetResponseValues::get() as usize;
+        if response.values.len() > max_values {
+            Err(IsmpError::ImplementationSpecific(format!(
+                "Get response contains {} values, maximum is {max_values}",
+                response.values.len()
+            )))?
+        }
+
+        let max_size = T::MaxGetResponseValueSize::get() as usize;
+        if let Some(value) = response.values.values().flatten().find(|value| value.len() > max_size)
+        {
+            Err(IsmpError::ImplementationSpecific(format!(
+                "Get response contains a value of {} bytes, maximum is {max_size}",
+                value.len()
+            )))?
+        }
+
+        Ok(())
+    }
+
+    /// Execute a module callback. If the callback reports the weight it consumed, it's charged
+    /// that in place of its declared weight.
+    fn metered<R>(&self, declared: Weight, callback: impl FnOnce() -> R) -> R {
+        let before = ModuleGasUsed::<T>::get();
+        let result = callback();
+        let used = ModuleGasUsed::<T>::get().saturating_sub(before);
+        if used != Weight::zero() {
+            WeightConsumed::<T>::mutate(|consumed| {
+                consumed.weight_used = consumed.weight_used.saturating_add(used);
+                consumed.weight_limit = consumed.weight_limit.saturating_add(declared);
+            });
+        }
+        result
+    }
+}
+
+impl<T: Config> IsmpModule for ValidatingModule<T> {
+    fn on_accept(&self, request: Post) -> Result<(), IsmpError> {
+        let declared = self.declared_weight(|weight| weight.on_accept(&request));
+        self.metered(declared, || self.module.on_accept(request))
+    }
+
+    fn on_response(&self, response: Response) -> Result<(), IsmpError> {
+        let response = match response {
+            Response::Post(response) => Response::Post(PostResponse {
+                post: PostEnvelope::open(response.post)?.0,
+                response: response.response,
+            }),
+            Response::Get(response) => {
+                Self::check_get_response(&response)?;
+                Response::Get(response)
+            }
+        };
+
+        let declared = self.declared_weight(|weight| weight.on_response(&response));
+        self.metered(declared, || self.module.on_response(response))
+    }
+
+    fn on_timeout(&self, request: Request) -> Result<(), IsmpError> {
+        let request = match request {
+            Request::Post(post) => Request::Post(PostEnvelope::open(post)?.0),
+            request => request,
+        };
+
+        let declared = self.declared_weight(|weight| weight.on_timeout(&request));
+        self.metered(declared, || self.module.on_timeout(request))
+    }
+}
+
+/// Delivers post requests addressed to [`ENVELOPE_MODULE_ID`] to the module named in their
+/// [`PostEnvelope`], acknowledging them if the sender asked for it
+struct EnvelopeModule<T>(PhantomData<T>);
+
+impl<T: Config> IsmpModule for EnvelopeModule<T> {
+    fn on_accept(&self, request: Post) -> Result<(), IsmpError> {
+        let (delivered, ack_requested) = PostEnvelope::open(request.clone())?;
+        let result = ValidatingRouter::<T>::default()
+            .module_for_id(delivered.to.clone())
+            .and_then(|module| module.on_accept(delivered));
+        if !ack_requested {
+            return result
+        }
+
+        // The acknowledgement commits to the request as it was sent, the module's error takes
+        // precedence over a failure to acknowledge
+        let code = if result.is_ok() { AckCode::Success } else { AckCode::Failure };
+        let ack = Pallet::<T>::dispatch_ack(request, code).map_err(IsmpError::from);
+        result.and(ack)
+    }
+
+    fn on_response(&self, _response: Response) -> Result<(), IsmpError> {
+        Err(IsmpError::ImplementationSpecific(
+            "The envelope module doesn't dispatch requests".to_string(),
+        ))
+    }
+
+    fn on_timeout(&self, _request: Request) -> Result<(), IsmpError> {
+        Err(IsmpError::ImplementationSpecific(
+            "The envelope module doesn't dispatch requests".to_string(),
+        ))
+    }
+}
+
+/// Handles cancellations of outgoing requests, see [`Dispatcher::cancel_request`]. At the
+/// destination, it stores a receipt for the cancelled request so it can no longer be delivered.
+/// At the source, it times out the cancelled request once the destination acknowledges this.
+struct CancellationModule<T>(PhantomData<T>);
+
+impl<T: Config> CancellationModule<T> {
+    /// Decode the request cancelled by a cancellation
+    fn cancelled_request(cancellation: &Post) -> Result<Post, IsmpError> {
+        if cancellation.from != CANCELLATION_MODULE_ID.0.to_vec() {
+            Err(IsmpError::ImplementationSpecific(
+                "Cancellations must be sent by the cancellation module".to_string(),
+            ))?
+        }
+
+        Post::decode_all(&mut &cancellation.data[..])
+            .map_err(|_| IsmpError::ImplementationSpecific("Invalid cancelled request".to_string()))
+    }
+}
+
+impl<T: Config> IsmpModule for CancellationModule<T> {
+    fn on_accept(&self, request: Post) -> Result<(), IsmpError> {
+        let cancelled = Self::cancelled_request(&request)?;
+        // Only the source of a request can cancel it
+        if cancelled.source != request.source || cancelled.dest != request.dest {
+            Err(IsmpError::ImplementationSpecific(
+                "Cancellation doesn't match the cancelled request".to_string(),
+            ))?
+        }
+
+        let commitment = hash_request::<Host<T>>(&Request::Post(cancelled));
+        if RequestReceipts::<T>::contains_key(commitment) {
+            Err(IsmpError::ImplementationSpecific(
+                "Cancelled request was already delivered".to_string(),
+            ))?
+        }
+        RequestReceipts::<T>::insert(commitment, Receipt::Ok);
+        Ok(())
+    }
+
+    fn on_response(&self, response: Response) -> Result<(), IsmpError> {
+        let PostResponse { post, response } = match response {
+            Response::Post(response) => response,
+            Response::Get(_) => Err(IsmpError::ImplementationSpecific(
+                "The cancellation module doesn't dispatch get requests".to_string(),
+            ))?,
+        };
+        let cancelled = Self::cancelled_request(&post)?;
+        let from = cancelled.from.clone();
+        let cancelled = Request::Post(cancelled);
+        let commitment = hash_request::<Host<T>>(&cancelled);
+        CancelledRequests::<T>::remove(commitment);
+
+        let code = AckCode::decode(&mut &response[..]).map_err(|_| {
+            IsmpError::ImplementationSpecific("Invalid cancellation acknowledgement".to_string())
+        })?;
+        // The request could have timed out while its cancellation was in flight
+        if code != AckCode::Success || !RequestCommitments::<T>::contains_key(commitment) {
+            return Ok(())
+        }
+
+        Host::<T>::default().delete_request_commitment(&cancelled)?;
+        Pallet::<T>::deposit_event(Event::RequestCancelled { commitment });
+        ValidatingRouter::<T>::default()
+            .module_for_id(from)
+            .and_then(|module| module.on_timeout(cancelled))
+    }
+
+    fn on_timeout(&self, request: Request) -> Result<(), IsmpError> {
+        // The destination didn't receive the cancellation, the request is left to time out
+        if let Request::Post(post) = request {
+            let cancelled = Self::cancelled_request(&post)?;
+            CancelledRequests::<T>::remove(hash_request::<Host<T>>(&Request::Post(cancelled)));
+        }
+        Ok(())
+    }
+}
@@ -14,12 +14,13 @@
 // limitations under the License.
 
 //! Ismp Errors conversions
+use crate::events::DisplayStateMachineId;
 use codec::{Decode, Encode};
 use ismp_rs::{
-    consensus::{ConsensusClientId, StateMachineHeight},
+    consensus::{ConsensusClientId, ConsensusStateId, StateMachineHeight, StateMachineId},
     error::Error as IsmpError,
     host::StateMachine,
-    module::DispatchResult,
+    module::{DispatchError, DispatchResult},
 };
 use sp_std::prelude::*;
 
@@ -95,6 +96,36 @@ pub enum HandlingError {
     },
     InsufficientProofHeight,
     ModuleNotFound(Vec<u8>),
+    ModuleDispatchError {
+        nonce: u64,
+        source: StateMachine,
+        dest: StateMachine,
+        msg: Vec<u8>,
+    },
+    UpdateIntervalNotElapsed {
+        id: ConsensusClientId,
+        update_time: u64,
+        current_time: u64,
+        min_interval: u64,
+    },
+    PausedConsensusClient {
+        id: ConsensusClientId,
+    },
+    PausedStateMachine {
+        id: StateMachineId,
+    },
+    ConsensusClientMismatch {
+        id: StateMachineId,
+        registered: ConsensusClientId,
+    },
+    FilteredMessage,
+    BatchTooLarge {
+        size: u32,
+        max: u32,
+    },
+    UnknownConsensusClient {
+        id: ConsensusStateId,
+    },
 }
 
 #[derive(Debug)]
@@ -104,6 +135,49 @@ pub enum ModuleCallbackResult {
     Timeout(Vec<DispatchResult>),
 }
 
+impl From<&DispatchError> for HandlingError {
+    fn from(value: &DispatchError) -> Self {
+        HandlingError::ModuleDispatchError {
+            nonce: value.nonce,
+            source: value.source_chain,
+            dest: value.dest_chain,
+            msg: value.msg.as_bytes().to_vec(),
+        }
+    }
+}
+
+impl core::fmt::Display for HandlingError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            HandlingError::PausedStateMachine { id } => {
+                write!(f, "State machine {} is paused", DisplayStateMachineId(id))
+            }
+            HandlingError::ConsensusClientMismatch { id, registered } => write!(
+                f,
+                "State machine {} is tracked by consensus state {}",
+                DisplayStateMachineId(id),
+                DisplayStateMachineId(&StateMachineId {
+                    state_id: id.state_id,
+                    consensus_state_id: *registered
+                })
+            ),
+            HandlingError::StateCommitmentNotFound { height } => write!(
+                f,
+                "State commitment not found for {} at {}",
+                DisplayStateMachineId(&height.id),
+                height.height
+            ),
+            HandlingError::FrozenStateMachine { height } => write!(
+                f,
+                "State machine {} is frozen at {}",
+                DisplayStateMachineId(&height.id),
+                height.height
+            ),
+            err => write!(f, "{err:?}"),
+        }
+    }
+}
+
 impl From<ismp_rs::error::Error> for HandlingError {
     fn from(value: ismp_rs::error::Error) -> Self {
         match value {
@@ -80,3 +80,18 @@ pub fn to_core_protocol_event<T: Config>(event: PalletEvent<T>) -> Option<Event>
         _ => None,
     }
 }
+
+/// Formats a [`StateMachineId`] for humans as the state machine followed by the consensus state
+/// id as ascii, e.g. `StateMachine::Polkadot(1000)/PARA`
+pub struct DisplayStateMachineId<'a>(pub &'a StateMachineId);
+
+impl core::fmt::Display for DisplayStateMachineId<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "StateMachine::{:?}/", self.0.state_id)?;
+        for byte in self.0.consensus_state_id {
+            // Non printable bytes are shown as escapes
+            write!(f, "{}", core::ascii::escape_default(byte))?;
+        }
+        Ok(())
+    }
+}
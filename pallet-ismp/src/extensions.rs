@@ -0,0 +1,98 @@
+// Copyright (C) 2023 Polytope Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Transaction extensions for pallet-ismp
+
+use crate::{Call, Config, Pallet};
+use codec::{Decode, Encode};
+use core::marker::PhantomData;
+use frame_support::traits::IsSubType;
+use scale_info::TypeInfo;
+use sp_runtime::{
+    traits::{DispatchInfoOf, SignedExtension},
+    transaction_validity::{
+        InvalidTransaction, TransactionValidity, TransactionValidityError, ValidTransaction,
+    },
+};
+
+/// Rejects ISMP messages signed by accounts that aren't in [`crate::AllowedRelayers`], unless
+/// no relayers have been allowed. Replays of included message transactions are already rejected
+/// by the runtime's `CheckNonce` and `CheckMortality` extensions, and requests or responses
+/// resubmitted in a new transaction are rejected by their receipts when they're handled.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+pub struct CheckIsmpPermissions<T>(PhantomData<T>);
+
+impl<T> CheckIsmpPermissions<T> {
+    /// Create the extension
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T> Default for CheckIsmpPermissions<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> core::fmt::Debug for CheckIsmpPermissions<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "CheckIsmpPermissions")
+    }
+}
+
+impl<T: Config + Send + Sync> SignedExtension for CheckIsmpPermissions<T>
+where
+    T::RuntimeCall: IsSubType<Call<T>>,
+{
+    const IDENTIFIER: &'static str = "CheckIsmpPermissions";
+    type AccountId = T::AccountId;
+    type Call = T::RuntimeCall;
+    type AdditionalSigned = ();
+    type Pre = ();
+
+    fn additional_signed(&self) -> Result<Self::AdditionalSigned, TransactionValidityError> {
+        Ok(())
+    }
+
+    fn validate(
+        &self,
+        who: &Self::AccountId,
+        call: &Self::Call,
+        _info: &DispatchInfoOf<Self::Call>,
+        _len: usize,
+    ) -> TransactionValidity {
+        let is_message = matches!(
+            call.is_sub_type(),
+            Some(Call::handle { .. }) | Some(Call::handle_consensus_batch { .. })
+        );
+        if is_message && !Pallet::<T>::is_allowed_relayer(who) {
+            Err(InvalidTransaction::BadSigner)?
+        }
+
+        Ok(ValidTransaction::default())
+    }
+
+    fn pre_dispatch(
+        self,
+        who: &Self::AccountId,
+        call: &Self::Call,
+        info: &DispatchInfoOf<Self::Call>,
+        len: usize,
+    ) -> Result<Self::Pre, TransactionValidityError> {
+        self.validate(who, call, info, len).map(|_| ())
+    }
+}
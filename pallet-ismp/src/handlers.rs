@@ -1,29 +1,40 @@
 //! Some extra utilities for pallet-ismp
 
 use crate::{
-    dispatcher::Receipt, host::Host, Config, Event, Pallet, RequestCommitments, ResponseCommitments,
+    dispatcher::{Dispatcher, Receipt},
+    host::Host,
+    primitives::{
+        AckCode, DispatchErrorKind, DispatchFailure, PostEnvelope, RequestKind, RequestMetadata,
+        CANCELLATION_MODULE_ID,
+    },
+    BlockGetRequests, CancelledRequests, Config, Event, OutgoingRequestsByDest, Pallet,
+    RequestCommitments, RequestNonceToBlock, RequestReceipts, RespondedRequests,
+    ResponseCommitments,
 };
-use alloc::string::ToString;
-use ismp_primitives::{mmr::Leaf, LeafIndexQuery};
+use codec::Encode;
+use ismp_primitives::mmr::Leaf;
 use ismp_rs::{
-    error::Error as IsmpError,
-    router::{Request, Response},
+    router::{DispatchPost, DispatchRequest, Post, PostResponse, Request, Response},
     util::{hash_request, hash_response},
 };
 
 impl<T: Config> Pallet<T> {
     /// Dispatch an outgoing request
-    pub fn dispatch_request(request: Request) -> Result<(), IsmpError> {
+    pub fn dispatch_request(request: Request) -> Result<(), DispatchFailure> {
         let commitment = hash_request::<Host<T>>(&request);
 
         if RequestCommitments::<T>::contains_key(commitment) {
-            Err(IsmpError::ImplementationSpecific("Duplicate request".to_string()))?
+            Err(DispatchFailure::new(DispatchErrorKind::Duplicate, "Duplicate request"))?
         }
 
         let (dest_chain, source_chain, nonce) =
             (request.dest_chain(), request.source_chain(), request.nonce());
+        let meta = RequestMetadata::from(&request);
         Pallet::<T>::mmr_push(Leaf::Request(request)).ok_or_else(|| {
-            IsmpError::ImplementationSpecific("Failed to push request into mmr".to_string())
+            DispatchFailure::new(
+                DispatchErrorKind::MmrPushFailed,
+                "Failed to push request into mmr",
+            )
         })?;
         // Deposit Event
         Pallet::<T>::deposit_event(Event::Request {
@@ -31,33 +42,68 @@ impl<T: Config> Pallet<T> {
             source_chain,
             dest_chain,
         });
+        RequestNonceToBlock::<T>::insert(nonce, frame_system::Pallet::<T>::block_number());
 
-        RequestCommitments::<T>::insert(
-            commitment,
-            LeafIndexQuery { source_chain, dest_chain, nonce },
-        );
+        if meta.kind == Some(RequestKind::Get) {
+            BlockGetRequests::<T>::append(commitment);
+        }
+        RequestCommitments::<T>::insert(commitment, meta);
+        OutgoingRequestsByDest::<T>::insert(dest_chain, commitment, ());
         Ok(())
     }
 
     /// Dispatch an outgoing response
-    pub fn dispatch_response(response: Response) -> Result<(), IsmpError> {
+    pub fn dispatch_response(response: Response) -> Result<(), DispatchFailure> {
+        // Responses can only be sent for incoming requests we've received
         let commitment = hash_request::<Host<T>>(&response.request());
 
-        if !RequestCommitments::<T>::contains_key(commitment) {
-            Err(IsmpError::ImplementationSpecific("Unknown request for response".to_string()))?
+        if !RequestReceipts::<T>::contains_key(commitment) {
+            Err(DispatchFailure::new(
+                DispatchErrorKind::UnknownRequest,
+                "Unknown request for response",
+            ))?
         }
 
+        // A request is only responded to once, this includes the acknowledgement of requests
+        // that asked for it
+        if RespondedRequests::<T>::contains_key(commitment) {
+            Err(DispatchFailure::new(
+                DispatchErrorKind::Duplicate,
+                "Request has already been responded to",
+            ))?
+        }
+
+        Self::commit_response(response)
+    }
+
+    /// Respond to an incoming post request with the outcome of delivering it to its module. This
+    /// is called while the request is being handled, before its receipt is stored. Nothing is
+    /// sent if the request was already responded to.
+    pub(crate) fn dispatch_ack(post: Post, code: AckCode) -> Result<(), DispatchFailure> {
+        let request = Request::Post(post.clone());
+        if RespondedRequests::<T>::contains_key(hash_request::<Host<T>>(&request)) {
+            return Ok(())
+        }
+
+        Self::commit_response(Response::Post(PostResponse { post, response: code.encode() }))
+    }
+
+    /// Commit an outgoing response to the mmr
+    fn commit_response(response: Response) -> Result<(), DispatchFailure> {
         let commitment = hash_response::<Host<T>>(&response);
 
         if ResponseCommitments::<T>::contains_key(commitment) {
-            Err(IsmpError::ImplementationSpecific("Duplicate response".to_string()))?
+            Err(DispatchFailure::new(DispatchErrorKind::Duplicate, "Duplicate response"))?
         }
 
         let (dest_chain, source_chain, nonce) =
             (response.dest_chain(), response.source_chain(), response.nonce());
 
         Pallet::<T>::mmr_push(Leaf::Response(response)).ok_or_else(|| {
-            IsmpError::ImplementationSpecific("Failed to push response into mmr".to_string())
+            DispatchFailure::new(
+                DispatchErrorKind::MmrPushFailed,
+                "Failed to push response into mmr",
+            )
         })?;
 
         Pallet::<T>::deposit_event(Event::Response {
@@ -65,7 +111,41 @@ impl<T: Config> Pallet<T> {
             dest_chain,
             source_chain,
         });
+        RespondedRequests::<T>::insert(hash_request::<Host<T>>(&response.request()), ());
         ResponseCommitments::<T>::insert(commitment, Receipt::Ok);
         Ok(())
     }
+
+    /// Cancel an outgoing post request that hasn't been delivered. A cancellation is sent to the
+    /// destination, which refuses to receive the request from then on and acknowledges it. The
+    /// request is timed out to its module once the acknowledgement is received, nothing happens
+    /// if the request was delivered first.
+    pub fn cancel_request(post: Post) -> Result<(), DispatchFailure> {
+        let commitment = hash_request::<Host<T>>(&Request::Post(post.clone()));
+        if !RequestCommitments::<T>::contains_key(commitment) {
+            Err(DispatchFailure::new(
+                DispatchErrorKind::UnknownRequest,
+                "Unknown request commitment for cancellation",
+            ))?
+        }
+        if CancelledRequests::<T>::contains_key(commitment) {
+            Err(DispatchFailure::new(DispatchErrorKind::Duplicate, "Request is already cancelled"))?
+        }
+
+        let cancellation = DispatchPost {
+            dest: post.dest,
+            from: CANCELLATION_MODULE_ID.0.to_vec(),
+            to: CANCELLATION_MODULE_ID.0.to_vec(),
+            timeout_timestamp: post.timeout_timestamp,
+            data: post.encode(),
+            gas_limit: 0,
+        };
+        // Cancellations are sent from the reserved cancellation module, which other modules
+        // can't dispatch requests from
+        Dispatcher::<T>::default().dispatch_unchecked(DispatchRequest::Post(
+            PostEnvelope::seal(cancellation, true, false),
+        ))?;
+        CancelledRequests::<T>::insert(commitment, ());
+        Ok(())
+    }
 }
@@ -15,23 +15,29 @@
 
 //! Host implementation for ISMP
 use crate::{
-    dispatcher::Receipt, primitives::ConsensusClientProvider, AllowedProxies, ChallengePeriod,
-    Config, ConsensusClientUpdateTime, ConsensusStateClient, ConsensusStates,
-    FrozenConsensusClients, FrozenHeights, LatestStateMachineHeight, Nonce, RequestCommitments,
-    RequestReceipts, ResponseReceipts, StateCommitments, StateMachineUpdateTime, UnbondingPeriod,
+    dispatcher::{Receipt, ValidatingRouter},
+    errors::HandlingError,
+    primitives::{decode_consensus_state, encode_consensus_state, ConsensusClientProvider},
+    AllowedProxies, ChallengePeriod, Config, ConsensusClientUpdateTime, ConsensusStateClient,
+    ConsensusStates, Event, FrozenConsensusClients, FrozenHeights, HostStateMachine,
+    LatestStateMachineHeight, Nonce, OutgoingRequestsByDest, Pallet, RequestCommitments,
+    RequestNonceToBlock, RequestReceipts, ResponseCommitments, ResponseReceipts, StateCommitments,
+    StateMachineConsensusClient, StateMachineUpdateTime, UnbondingPeriod,
 };
-use alloc::{format, string::ToString};
-use core::time::Duration;
+use alloc::{collections::BTreeMap, format, rc::Rc, string::ToString};
+use codec::Encode;
+use core::{cell::RefCell, marker::PhantomData, time::Duration};
 use frame_support::traits::{Get, UnixTime};
 use ismp_rs::{
     consensus::{
-        ConsensusClient, ConsensusClientId, ConsensusStateId, StateCommitment, StateMachineHeight,
-        StateMachineId,
+        ConsensusClient, ConsensusClientId, ConsensusStateId, StateCommitment, StateMachineClient,
+        StateMachineHeight, StateMachineId, VerifiedCommitments,
     },
     error::Error,
     host::{IsmpHost, StateMachine},
-    router::{IsmpRouter, Request},
-    util::hash_request,
+    messaging::Proof,
+    router::{IsmpRouter, Request, RequestResponse, Response},
+    util::{hash_request, hash_response},
 };
 use sp_core::H256;
 use sp_runtime::SaturatedConversion;
@@ -39,17 +45,86 @@ use sp_std::prelude::*;
 
 /// An implementation for the IsmpHost
 #[derive(Clone)]
-pub struct Host<T: Config>(core::marker::PhantomData<T>);
+pub struct Host<T: Config> {
+    /// State commitments that have been read or written through this host. A host lives for the
+    /// duration of a single `handle` call, so this deduplicates storage reads for messages
+    /// proven against the same height.
+    commitments: RefCell<BTreeMap<StateMachineHeight, StateCommitment>>,
+    /// Values verified by state proofs through this host, shared with the consensus clients it
+    /// hands out
+    state_proofs: VerifiedStateProofs,
+    /// Whether this host seeds the initial heights of a consensus state that's being created
+    seeding: bool,
+    _phantom: PhantomData<T>,
+}
+
+/// Values verified by state proofs, keyed by the hash of the state commitment they were verified
+/// against and the queried key
+pub type VerifiedStateProofs = Rc<RefCell<BTreeMap<(H256, Vec<u8>), Option<Vec<u8>>>>>;
 
 impl<T: Config> Default for Host<T> {
     fn default() -> Self {
-        Self(core::marker::PhantomData)
+        Self {
+            commitments: Default::default(),
+            state_proofs: Default::default(),
+            seeding: false,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T: Config> Host<T> {
+    /// Returns a host for creating a consensus state. The initial heights seeded when a consensus
+    /// state is created are accepted from any consensus state.
+    pub fn seeding() -> Self {
+        Self { seeding: true, ..Default::default() }
+    }
+
+    /// Only the consensus state registered for a state machine can advance it
+    fn ensure_registered_consensus_state(&self, height: &StateMachineHeight) -> Result<(), Error> {
+        if self.seeding {
+            return Ok(())
+        }
+        match StateMachineConsensusClient::<T>::get(height.id.state_id) {
+            Some(registered) if registered != height.id.consensus_state_id => {
+                Err(Error::ImplementationSpecific(
+                    HandlingError::ConsensusClientMismatch { id: height.id, registered }
+                        .to_string(),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the commitment of an outgoing response if it was dispatched by this host
+    pub fn response_commitment(&self, res: &Response) -> Result<H256, Error> {
+        let commitment = hash_response::<Self>(res);
+        let _ = ResponseCommitments::<T>::get(commitment).ok_or_else(|| {
+            Error::ImplementationSpecific("Response commitment not found".to_string())
+        })?;
+
+        Ok(commitment)
+    }
+
+    /// Deletes the commitment of an outgoing response
+    pub fn delete_response_commitment(&self, res: &Response) -> Result<(), Error> {
+        ResponseCommitments::<T>::remove(hash_response::<Self>(res));
+        Ok(())
+    }
+
+    /// Returns the state commitments at the given heights in the same order, reading through the
+    /// host's cache
+    pub fn state_machine_commitments(
+        &self,
+        heights: Vec<StateMachineHeight>,
+    ) -> Vec<Option<StateCommitment>> {
+        heights.into_iter().map(|height| self.state_machine_commitment(height).ok()).collect()
     }
 }
 
 impl<T: Config> IsmpHost for Host<T> {
     fn host_state_machine(&self) -> StateMachine {
-        T::StateMachine::get()
+        HostStateMachine::<T>::get().unwrap_or_else(T::StateMachine::get)
     }
 
     fn latest_commitment_height(&self, id: StateMachineId) -> Result<u64, Error> {
@@ -60,12 +135,20 @@ impl<T: Config> IsmpHost for Host<T> {
         &self,
         height: StateMachineHeight,
     ) -> Result<StateCommitment, Error> {
-        StateCommitments::<T>::get(height).ok_or_else(|| Error::StateCommitmentNotFound { height })
+        let cached = self.commitments.borrow().get(&height).cloned();
+        if let Some(commitment) = cached {
+            return Ok(commitment)
+        }
+
+        let commitment = StateCommitments::<T>::get(&height)
+            .ok_or_else(|| Error::StateCommitmentNotFound { height: height.clone() })?;
+        self.commitments.borrow_mut().insert(height, commitment.clone());
+        Ok(commitment)
     }
 
     fn consensus_update_time(&self, id: ConsensusClientId) -> Result<Duration, Error> {
         ConsensusClientUpdateTime::<T>::get(id)
-            .map(|timestamp| Duration::from_secs(timestamp))
+            .map(|timestamp| Duration::from_millis(timestamp))
             .ok_or_else(|| {
                 Error::ImplementationSpecific(format!("Update time not found for {:?}", id))
             })
@@ -86,8 +169,11 @@ impl<T: Config> IsmpHost for Host<T> {
     }
 
     fn consensus_state(&self, id: ConsensusClientId) -> Result<Vec<u8>, Error> {
-        ConsensusStates::<T>::get(id)
-            .ok_or_else(|| Error::ConsensusStateNotFound { consensus_state_id: id })
+        let stored = ConsensusStates::<T>::get(id)
+            .ok_or_else(|| Error::ConsensusStateNotFound { consensus_state_id: id })?;
+        decode_consensus_state(stored).ok_or_else(|| {
+            Error::ImplementationSpecific(format!("Unsupported consensus state version for {id:?}"))
+        })
     }
 
     fn timestamp(&self) -> Duration {
@@ -117,7 +203,14 @@ impl<T: Config> IsmpHost for Host<T> {
     }
 
     fn store_consensus_state(&self, id: ConsensusClientId, state: Vec<u8>) -> Result<(), Error> {
-        ConsensusStates::<T>::insert(id, state);
+        let max = T::MaxConsensusStateBytes::get() as usize;
+        if state.len() > max {
+            Err(Error::ImplementationSpecific(format!(
+                "Consensus state for {id:?} is {} bytes, maximum is {max}",
+                state.len()
+            )))?
+        }
+        ConsensusStates::<T>::insert(id, encode_consensus_state(state));
         Ok(())
     }
 
@@ -126,7 +219,7 @@ impl<T: Config> IsmpHost for Host<T> {
         id: ConsensusClientId,
         timestamp: Duration,
     ) -> Result<(), Error> {
-        ConsensusClientUpdateTime::<T>::insert(id, timestamp.as_secs().saturated_into::<u64>());
+        ConsensusClientUpdateTime::<T>::insert(id, timestamp.as_millis().saturated_into::<u64>());
         Ok(())
     }
 
@@ -135,6 +228,7 @@ impl<T: Config> IsmpHost for Host<T> {
         state_machine_height: StateMachineHeight,
         timestamp: Duration,
     ) -> Result<(), Error> {
+        self.ensure_registered_consensus_state(&state_machine_height)?;
         StateMachineUpdateTime::<T>::insert(
             state_machine_height,
             timestamp.as_secs().saturated_into::<u64>(),
@@ -147,6 +241,23 @@ impl<T: Config> IsmpHost for Host<T> {
         height: StateMachineHeight,
         state: StateCommitment,
     ) -> Result<(), Error> {
+        // This is the first write for a new height, so nothing is stored for a height that's
+        // rejected
+        self.ensure_registered_consensus_state(&height)?;
+        // A different commitment for a height that's already tracked is byzantine behaviour. The
+        // freeze must outlive the message, an error would roll it back with the rest of a
+        // transactional batch, so the conflicting commitment is dropped and reported instead.
+        if let Some(existing) = StateCommitments::<T>::get(&height) {
+            if existing != state {
+                self.freeze_consensus_client(height.id.consensus_state_id)?;
+                Pallet::<T>::deposit_event(Event::<T>::ConsensusClientFrozen {
+                    consensus_state_id: height.id.consensus_state_id,
+                    height,
+                });
+                return Ok(())
+            }
+        }
+        self.commitments.borrow_mut().insert(height.clone(), state.clone());
         StateCommitments::<T>::insert(height, state);
         Ok(())
     }
@@ -157,6 +268,7 @@ impl<T: Config> IsmpHost for Host<T> {
     }
 
     fn store_latest_commitment_height(&self, height: StateMachineHeight) -> Result<(), Error> {
+        self.ensure_registered_consensus_state(&height)?;
         LatestStateMachineHeight::<T>::insert(height.id, height.height);
         Ok(())
     }
@@ -165,6 +277,10 @@ impl<T: Config> IsmpHost for Host<T> {
         let hash = hash_request::<Self>(req);
         // We can't delete actual leaves in the mmr so this serves as a replacement for that
         RequestCommitments::<T>::remove(hash);
+        OutgoingRequestsByDest::<T>::remove(req.dest_chain(), hash);
+        if req.source_chain() == self.host_state_machine() {
+            RequestNonceToBlock::<T>::remove(req.nonce());
+        }
         Ok(())
     }
 
@@ -175,7 +291,8 @@ impl<T: Config> IsmpHost for Host<T> {
     }
 
     fn consensus_client(&self, id: ConsensusClientId) -> Result<Box<dyn ConsensusClient>, Error> {
-        <T as Config>::ConsensusClientProvider::consensus_client(id)
+        let client = <T as Config>::ConsensusClientProvider::consensus_client(id)?;
+        Ok(Box::new(CachingConsensusClient::new(client, self.state_proofs.clone())))
     }
 
     fn challenge_period(&self, id: ConsensusStateId) -> Option<Duration> {
@@ -183,7 +300,7 @@ impl<T: Config> IsmpHost for Host<T> {
     }
 
     fn ismp_router(&self) -> Box<dyn IsmpRouter> {
-        Box::new(T::IsmpRouter::default())
+        Box::new(ValidatingRouter::<T>::default())
     }
 
     fn is_state_machine_frozen(&self, machine: StateMachineHeight) -> Result<(), Error> {
@@ -276,6 +393,111 @@ impl<T: Config> IsmpHost for Host<T> {
     }
 }
 
+/// Wraps a consensus client, caching the results of state proof verification done by its state
+/// machine clients in the host that created it
+pub struct CachingConsensusClient {
+    client: Box<dyn ConsensusClient>,
+    state_proofs: VerifiedStateProofs,
+}
+
+impl CachingConsensusClient {
+    /// Wrap the given consensus client
+    pub fn new(client: Box<dyn ConsensusClient>, state_proofs: VerifiedStateProofs) -> Self {
+        Self { client, state_proofs }
+    }
+}
+
+impl ConsensusClient for CachingConsensusClient {
+    fn verify_consensus(
+        &self,
+        host: &dyn IsmpHost,
+        consensus_state_id: ConsensusStateId,
+        trusted_consensus_state: Vec<u8>,
+        proof: Vec<u8>,
+    ) -> Result<(Vec<u8>, VerifiedCommitments), Error> {
+        self.client.verify_consensus(host, consensus_state_id, trusted_consensus_state, proof)
+    }
+
+    fn verify_fraud_proof(
+        &self,
+        host: &dyn IsmpHost,
+        trusted_consensus_state: Vec<u8>,
+        proof_1: Vec<u8>,
+        proof_2: Vec<u8>,
+    ) -> Result<(), Error> {
+        self.client.verify_fraud_proof(host, trusted_consensus_state, proof_1, proof_2)
+    }
+
+    fn state_machine(&self, id: StateMachine) -> Result<Box<dyn StateMachineClient>, Error> {
+        let state_machine = self.client.state_machine(id)?;
+        Ok(Box::new(CachingStateMachine::new(state_machine, self.state_proofs.clone())))
+    }
+}
+
+/// Wraps a state machine client, values verified by [`StateMachineClient::verify_state_proof`]
+/// are cached in memory so keys that were already read against the same state commitment by the
+/// same host aren't verified again.
+pub struct CachingStateMachine {
+    state_machine: Box<dyn StateMachineClient>,
+    state_proofs: VerifiedStateProofs,
+}
+
+impl CachingStateMachine {
+    /// Wrap the given state machine client
+    pub fn new(
+        state_machine: Box<dyn StateMachineClient>,
+        state_proofs: VerifiedStateProofs,
+    ) -> Self {
+        Self { state_machine, state_proofs }
+    }
+}
+
+impl StateMachineClient for CachingStateMachine {
+    fn verify_membership(
+        &self,
+        host: &dyn IsmpHost,
+        item: RequestResponse,
+        root: StateCommitment,
+        proof: &Proof,
+    ) -> Result<(), Error> {
+        self.state_machine.verify_membership(host, item, root, proof)
+    }
+
+    fn state_trie_key(&self, requests: Vec<Request>) -> Vec<Vec<u8>> {
+        self.state_machine.state_trie_key(requests)
+    }
+
+    fn verify_state_proof(
+        &self,
+        host: &dyn IsmpHost,
+        keys: Vec<Vec<u8>>,
+        root: StateCommitment,
+        proof: &Proof,
+    ) -> Result<BTreeMap<Vec<u8>, Option<Vec<u8>>>, Error> {
+        let commitment: H256 = sp_io::hashing::keccak_256(&root.encode()).into();
+        let mut values = BTreeMap::new();
+        let mut uncached = Vec::new();
+        for key in keys {
+            match self.state_proofs.borrow().get(&(commitment, key.clone())).cloned() {
+                Some(value) => {
+                    values.insert(key, value);
+                }
+                None => uncached.push(key),
+            }
+        }
+
+        if !uncached.is_empty() {
+            let verified = self.state_machine.verify_state_proof(host, uncached, root, proof)?;
+            for (key, value) in verified {
+                self.state_proofs.borrow_mut().insert((commitment, key.clone()), value.clone());
+                values.insert(key, value);
+            }
+        }
+
+        Ok(values)
+    }
+}
+
 impl<T: Config> ismp_rs::util::Keccak256 for Host<T> {
     fn keccak256(bytes: &[u8]) -> H256
     where
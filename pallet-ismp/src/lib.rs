@@ -23,11 +23,15 @@ extern crate alloc;
 extern crate core;
 
 pub mod benchmarking;
+#[cfg(feature = "compression")]
+pub mod compression;
 pub mod dispatcher;
 mod errors;
 pub mod events;
+pub mod extensions;
 pub mod handlers;
 pub mod host;
+pub mod migrations;
 mod mmr;
 #[cfg(any(feature = "runtime-benchmarks", feature = "testing", test))]
 pub mod mocks;
@@ -35,22 +39,28 @@ pub mod primitives;
 #[cfg(test)]
 pub mod tests;
 pub mod weight_info;
+#[cfg(feature = "xcm")]
+pub mod xcm_bridge;
 
 pub use mmr::utils::NodesUtils;
 
 use crate::host::Host;
+use alloc::collections::BTreeSet;
 use codec::{Decode, Encode};
 use core::time::Duration;
 use frame_support::{
     dispatch::{DispatchResult, DispatchResultWithPostInfo, Pays, PostDispatchInfo},
+    ensure,
     traits::{Get, UnixTime},
+    weights::Weight,
 };
 use ismp_rs::{
-    consensus::{ConsensusClientId, StateMachineId},
+    consensus::{ConsensusClientId, ConsensusStateId, StateMachineId},
     handlers::{handle_incoming_message, MessageResult},
     host::StateMachine,
     messaging::CreateConsensusState,
     router::{Request, Response},
+    util::{hash_request, hash_response},
 };
 use log::debug;
 use sp_core::{offchain::StorageKind, H256};
@@ -58,6 +68,7 @@ use sp_core::{offchain::StorageKind, H256};
 use crate::{
     errors::{HandlingError, ModuleCallbackResult},
     mmr::mmr::Mmr,
+    primitives::{BlockLeaves, ConsensusClientProvider, FilterMessage},
     weight_info::get_weight,
 };
 use frame_system::pallet_prelude::BlockNumberFor;
@@ -65,9 +76,17 @@ use ismp_primitives::{
     mmr::{DataOrHash, Leaf, LeafIndex, NodeIndex},
     LeafIndexQuery,
 };
-use ismp_rs::{consensus::StateMachineHeight, host::IsmpHost, messaging::Message};
+use ismp_rs::{
+    consensus::{StateCommitment, StateMachineHeight},
+    host::IsmpHost,
+    messaging::{Message, ResponseMessage, TimeoutMessage},
+};
 pub use pallet::*;
-use sp_runtime::RuntimeDebug;
+use sp_runtime::{
+    traits::{One, Zero},
+    transaction_validity::{InvalidTransaction, TransactionValidityError},
+    RuntimeDebug, SaturatedConversion,
+};
 use sp_std::prelude::*;
 
 // Definition of the pallet logic, to be aggregated at runtime definition through
@@ -83,7 +102,6 @@ pub mod pallet {
         primitives::{ConsensusClientProvider, WeightUsed},
         weight_info::{WeightInfo, WeightProvider},
     };
-    use alloc::collections::BTreeSet;
     use frame_support::{pallet_prelude::*, traits::UnixTime};
     use frame_system::pallet_prelude::*;
     use ismp_primitives::{
@@ -129,11 +147,72 @@ pub mod pallet {
 
         /// Weight provider for consensus clients and module callbacks
         type WeightProvider: WeightProvider;
+
+        /// Maximum number of state machine updates that can be pending finalization for a
+        /// consensus state. The oldest updates are evicted once this is exceeded.
+        #[pallet::constant]
+        type MaxPendingConsensusUpdates: Get<u32>;
+
+        /// Maximum number of consensus states whose pending updates are checked for an elapsed
+        /// challenge period in a single block, the next block continues where it stopped
+        #[pallet::constant]
+        type MaxConsensusStatesFinalizedPerBlock: Get<u32>;
+
+        /// Maximum number of values a get response can deliver to a module
+        #[pallet::constant]
+        type MaxGetResponseValues: Get<u32>;
+
+        /// Maximum size in bytes of a single value in a get response
+        #[pallet::constant]
+        type MaxGetResponseValueSize: Get<u32>;
+
+        /// Maximum number of requests a single timeout message can time out
+        #[pallet::constant]
+        type MaxTimeoutBatchSize: Get<u32>;
+
+        /// Maximum size in bytes of a consensus state, larger states are rejected when stored
+        #[pallet::constant]
+        type MaxConsensusStateBytes: Get<u32>;
+
+        /// Maximum difference in seconds tolerated between the host timestamp and the timestamp of
+        /// newly verified state commitments from trusted state machines before a
+        /// [`Event::TimestampDriftDetected`] warning is emitted.
+        #[pallet::constant]
+        type MaxTimestampDrift: Get<u64>;
+
+        /// Number of blocks for which historical MMR roots are retained in storage
+        #[pallet::constant]
+        type RootHistoryDepth: Get<BlockNumberFor<Self>>;
+
+        /// Maximum number of MMR leaves indexed by [`LeavesAtBlock`] for a single block
+        #[pallet::constant]
+        type MaxLeavesPerBlock: Get<u32>;
+
+        /// Whether the offchain worker publishes [`primitives::RelayerHints`] every block
+        #[pallet::constant]
+        type PublishRelayerHints: Get<bool>;
+
+        /// Whether messages can be submitted as unsigned transactions through
+        /// [`Pallet::handle_unsigned`]
+        #[pallet::constant]
+        type AllowUnsignedHandle: Get<bool>;
+
+        /// Number of blocks an unsigned message transaction stays valid in the transaction pool
+        #[pallet::constant]
+        type UnsignedLongevity: Get<u64>;
+
+        /// Decides which kinds of messages this chain accepts, messages it rejects fail with
+        /// [`HandlingError::FilteredMessage`]
+        type MessageFilter: primitives::FilterMessage;
     }
 
     // Simple declaration of the `Pallet` type. It is placeholder we use to implement traits and
     // method.
+    /// The current storage version
+    const STORAGE_VERSION: StorageVersion = StorageVersion::new(4);
+
     #[pallet::pallet]
+    #[pallet::storage_version(STORAGE_VERSION)]
     #[pallet::without_storage_info]
     pub struct Pallet<T>(_);
 
@@ -142,6 +221,28 @@ pub mod pallet {
     #[pallet::getter(fn mmr_root_hash)]
     pub type RootHash<T: Config> = StorageValue<_, H256, ValueQuery>;
 
+    /// MMR root hashes of recent blocks, retained for `T::RootHistoryDepth` blocks
+    #[pallet::storage]
+    #[pallet::getter(fn historical_roots)]
+    pub type HistoricalRoots<T: Config> =
+        StorageMap<_, Blake2_128Concat, BlockNumberFor<T>, H256, OptionQuery>;
+
+    /// Positions in the MMR of the leaves pushed in recent blocks, retained for
+    /// `T::RootHistoryDepth` blocks
+    #[pallet::storage]
+    pub type LeavesAtBlock<T: Config> = StorageMap<
+        _,
+        Blake2_128Concat,
+        BlockNumberFor<T>,
+        BoundedVec<LeafIndex, T::MaxLeavesPerBlock>,
+        ValueQuery,
+    >;
+
+    /// Recent blocks that pushed more leaves than [`LeavesAtBlock`] could index
+    #[pallet::storage]
+    pub type TruncatedLeavesAtBlock<T: Config> =
+        StorageMap<_, Blake2_128Concat, BlockNumberFor<T>, bool, ValueQuery>;
+
     /// Current size of the MMR (number of leaves) for requests.
     #[pallet::storage]
     #[pallet::getter(fn number_of_leaves)]
@@ -195,6 +296,12 @@ pub mod pallet {
     pub type ChallengePeriod<T: Config> =
         StorageMap<_, Blake2_128Concat, ConsensusStateId, u64, OptionQuery>;
 
+    /// A mapping of ConsensusStateId to the minimum interval in seconds between consensus updates
+    #[pallet::storage]
+    #[pallet::getter(fn min_update_interval)]
+    pub type MinUpdateInterval<T: Config> =
+        StorageMap<_, Blake2_128Concat, ConsensusStateId, u64, OptionQuery>;
+
     /// Holds a map of consensus clients frozen due to byzantine
     /// behaviour
     #[pallet::storage]
@@ -213,7 +320,47 @@ pub mod pallet {
     #[pallet::getter(fn allowed_proxies)]
     pub type AllowedProxies<T: Config> = StorageValue<_, Vec<StateMachine>, ValueQuery>;
 
-    /// Holds the timestamp at which a consensus client was recently updated.
+    /// Accounts allowed to submit consensus messages, anyone can submit them if this is empty
+    #[pallet::storage]
+    #[pallet::getter(fn allowed_consensus_submitters)]
+    pub type AllowedConsensusSubmitters<T: Config> =
+        StorageValue<_, BTreeSet<T::AccountId>, ValueQuery>;
+
+    /// Human readable names of consensus clients
+    #[pallet::storage]
+    pub type ConsensusClientNames<T: Config> =
+        StorageMap<_, Twox64Concat, ConsensusClientId, BoundedVec<u8, ConstU32<32>>, OptionQuery>;
+
+    /// Accounts allowed to submit ISMP messages through [`extensions::CheckIsmpPermissions`],
+    /// anyone can submit them if this is empty
+    #[pallet::storage]
+    pub type AllowedRelayers<T: Config> =
+        StorageMap<_, Blake2_128Concat, T::AccountId, (), OptionQuery>;
+
+    /// The block in which the outgoing request with a given nonce was dispatched
+    #[pallet::storage]
+    #[pallet::getter(fn request_nonce_to_block)]
+    pub type RequestNonceToBlock<T: Config> =
+        StorageMap<_, Blake2_128Concat, u64, BlockNumberFor<T>, OptionQuery>;
+
+    /// State machines whose messages are currently rejected
+    #[pallet::storage]
+    #[pallet::getter(fn paused_state_machines)]
+    pub type PausedStateMachines<T: Config> =
+        StorageMap<_, Blake2_128Concat, StateMachineId, bool, ValueQuery>;
+
+    /// Consensus clients whose messages are currently rejected
+    #[pallet::storage]
+    #[pallet::getter(fn paused_consensus_clients)]
+    pub type PausedConsensusClients<T: Config> =
+        StorageMap<_, Blake2_128Concat, ConsensusStateId, bool, ValueQuery>;
+
+    /// Whether message processing has been halted
+    #[pallet::storage]
+    #[pallet::getter(fn paused)]
+    pub type Paused<T: Config> = StorageValue<_, bool, ValueQuery>;
+
+    /// Holds the timestamp in milliseconds at which a consensus client was recently updated.
     /// Used in ensuring that the configured challenge period elapses.
     #[pallet::storage]
     #[pallet::getter(fn consensus_update_time)]
@@ -232,7 +379,12 @@ pub mod pallet {
     #[pallet::storage]
     #[pallet::getter(fn request_commitments)]
     pub type RequestCommitments<T: Config> =
-        StorageMap<_, Identity, H256, LeafIndexQuery, OptionQuery>;
+        StorageMap<_, Identity, H256, primitives::RequestMetadata, OptionQuery>;
+
+    /// Index of outgoing request commitments by their destination chain
+    #[pallet::storage]
+    pub type OutgoingRequestsByDest<T: Config> =
+        StorageDoubleMap<_, Blake2_128Concat, StateMachine, Identity, H256, (), OptionQuery>;
 
     /// Commitments for outgoing responses
     /// The key is the response commitment
@@ -240,6 +392,10 @@ pub mod pallet {
     #[pallet::getter(fn response_commitments)]
     pub type ResponseCommitments<T: Config> = StorageMap<_, Identity, H256, Receipt, OptionQuery>;
 
+    /// Commitments of the incoming requests that have been responded to
+    #[pallet::storage]
+    pub type RespondedRequests<T: Config> = StorageMap<_, Identity, H256, (), OptionQuery>;
+
     /// Receipts for incoming requests
     /// The key is the request commitment
     #[pallet::storage]
@@ -252,18 +408,30 @@ pub mod pallet {
     #[pallet::getter(fn response_receipts)]
     pub type ResponseReceipts<T: Config> = StorageMap<_, Identity, H256, Receipt, OptionQuery>;
 
-    /// Consensus update results still in challenge period
-    /// Set contains a tuple of previous height and latest height
+    /// Consensus update results still in challenge period, keyed by consensus state id
+    /// Set contains a tuple of the time in milliseconds the update was made, previous height and
+    /// latest height
     #[pallet::storage]
     #[pallet::getter(fn consensus_update_results)]
     pub type ConsensusUpdateResults<T: Config> = StorageMap<
         _,
         Twox64Concat,
-        ConsensusClientId,
-        BTreeSet<(StateMachineHeight, StateMachineHeight)>,
+        ConsensusStateId,
+        BTreeSet<(u64, StateMachineHeight, StateMachineHeight)>,
         OptionQuery,
     >;
 
+    /// The consensus state whose pending updates were checked last, the next block continues
+    /// checking [`ConsensusUpdateResults`] after it
+    #[pallet::storage]
+    pub type ConsensusUpdateResultsCursor<T: Config> =
+        StorageValue<_, ConsensusStateId, OptionQuery>;
+
+    /// Overrides the `T::StateMachine` identifier of the host when set
+    #[pallet::storage]
+    #[pallet::getter(fn host_state_machine_override)]
+    pub type HostStateMachine<T: Config> = StorageValue<_, StateMachine, OptionQuery>;
+
     /// Latest nonce for messages sent from this chain
     #[pallet::storage]
     #[pallet::getter(fn nonce)]
@@ -275,40 +443,110 @@ pub mod pallet {
     #[pallet::getter(fn weight_consumed)]
     pub type WeightConsumed<T: Config> = StorageValue<_, WeightUsed, ValueQuery>;
 
+    /// Weight reported by module callbacks through [`Pallet::record_module_gas`] in the current
+    /// transaction
+    #[pallet::storage]
+    pub type ModuleGasUsed<T: Config> = StorageValue<_, Weight, ValueQuery>;
+
+    /// The consensus state registered as the source of truth for a state machine. Requests and
+    /// responses from the state machine must be proven against this consensus state.
+    #[pallet::storage]
+    pub type StateMachineConsensusClient<T: Config> =
+        StorageMap<_, Blake2_128Concat, StateMachine, ConsensusStateId, OptionQuery>;
+
+    /// Commitments of outgoing requests cancelled by their source module, whose cancellation
+    /// hasn't been acknowledged by their destination yet
+    #[pallet::storage]
+    pub type CancelledRequests<T: Config> = StorageMap<_, Identity, H256, (), OptionQuery>;
+
+    /// Number of requests and responses pushed to the mmr in the current block
+    #[pallet::storage]
+    pub type BlockMessageCounts<T: Config> = StorageValue<_, (u32, u32), ValueQuery>;
+
+    /// Commitments of the GET requests dispatched in the current block. They're indexed offchain
+    /// in `on_finalize`, so it's never committed to the state.
+    #[pallet::storage]
+    pub type BlockGetRequests<T: Config> = StorageValue<_, Vec<H256>, ValueQuery>;
+
     // Pallet implements [`Hooks`] trait to define some logic to execute in some context.
     #[pallet::hooks]
     impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
         fn on_initialize(_n: BlockNumberFor<T>) -> Weight {
+            let finalization_weight = Self::finalize_elapsed_consensus_updates();
             // return Mmr finalization weight here
             <T as Config>::WeightInfo::on_finalize(Self::number_of_leaves() as u32)
+                .saturating_add(<T as frame_system::Config>::DbWeight::get().writes(3))
+                .saturating_add(finalization_weight)
         }
 
-        fn on_finalize(_n: BlockNumberFor<T>) {
+        fn on_runtime_upgrade() -> Weight {
+            crate::migrations::migrate::<T>()
+        }
+
+        fn on_finalize(n: BlockNumberFor<T>) {
+            // The offchain worker caches the state commitments GET requests are answered at
+            let get_requests = BlockGetRequests::<T>::take();
+            if !get_requests.is_empty() {
+                sp_io::offchain_index::set(
+                    &Self::get_requests_offchain_key(n),
+                    &get_requests.encode(),
+                );
+            }
+
+            // The history of a block only has entries if the mmr was modified in that block
+            let depth = T::RootHistoryDepth::get();
+            if n >= depth && HistoricalRoots::<T>::contains_key(n - depth) {
+                HistoricalRoots::<T>::remove(n - depth);
+                LeavesAtBlock::<T>::remove(n - depth);
+                TruncatedLeavesAtBlock::<T>::remove(n - depth);
+            }
+
             // Only finalize if mmr was modified
+            let (requests, responses) = BlockMessageCounts::<T>::take();
+            if requests == 0 && responses == 0 {
+                let digest = sp_runtime::generic::DigestItem::Consensus(
+                    ISMP_ID,
+                    RootHash::<T>::get().encode(),
+                );
+                <frame_system::Pallet<T>>::deposit_log(digest);
+                return
+            }
+
             let leaves = Self::number_of_leaves();
-            let root = if leaves != 0 {
-                let mmr: Mmr<mmr::storage::RuntimeStorage, T> = Mmr::new(leaves);
-                // Update the size, `mmr.finalize()` should also never fail.
-                let root = match mmr.finalize() {
-                    Ok(root) => root,
-                    Err(e) => {
-                        log::error!(target: "runtime::mmr", "MMR finalize failed: {:?}", e);
-                        return
-                    }
-                };
+            // Record the leaf count so proofs can later be generated against this block's root
+            sp_io::offchain_index::set(&Self::leaf_count_offchain_key(n), &leaves.encode());
+            let mmr: Mmr<mmr::storage::RuntimeStorage, T> = Mmr::new(leaves);
+            // Update the size, `mmr.finalize()` should also never fail.
+            let root = match mmr.finalize() {
+                Ok(root) => root,
+                Err(e) => {
+                    log::error!(target: "runtime::mmr", "MMR finalize failed: {:?}", e);
+                    return
+                }
+            };
 
-                <RootHash<T>>::put(root);
+            <RootHash<T>>::put(root);
+            HistoricalRoots::<T>::insert(n, root);
 
-                root
-            } else {
-                H256::default()
-            };
+            Self::deposit_event(Event::<T>::BlockStats { requests, responses, mmr_root: root });
 
             let digest = sp_runtime::generic::DigestItem::Consensus(ISMP_ID, root.encode());
             <frame_system::Pallet<T>>::deposit_log(digest);
         }
 
-        fn offchain_worker(_n: BlockNumberFor<T>) {}
+        fn offchain_worker(n: BlockNumberFor<T>) {
+            let pending_get_requests = Self::cache_pending_state_commitments(n);
+            if T::PublishRelayerHints::get() {
+                Self::publish_relayer_hints(n, &pending_get_requests);
+            }
+        }
+
+        fn integrity_test() {
+            assert!(
+                crate::migrations::call_indices_unchanged::<T>(),
+                "pallet-ismp call indices must never change"
+            );
+        }
     }
 
     /// Params to update the unbonding period for a consensus state
@@ -320,6 +558,8 @@ pub mod pallet {
         pub unbonding_period: Option<u64>,
         /// Challenge period duration
         pub challenge_period: Option<u64>,
+        /// Minimum interval between consensus updates
+        pub min_update_interval: Option<u64>,
     }
 
     #[pallet::call]
@@ -329,7 +569,13 @@ pub mod pallet {
         #[pallet::call_index(0)]
         #[frame_support::transactional]
         pub fn handle(origin: OriginFor<T>, messages: Vec<Message>) -> DispatchResultWithPostInfo {
-            let _ = ensure_signed(origin)?;
+            ensure!(!messages.is_empty(), Error::<T>::InvalidMessage);
+            let who = ensure_signed(origin)?;
+            ensure!(!Paused::<T>::get(), Error::<T>::Paused);
+
+            if messages.iter().any(|message| matches!(message, Message::Consensus(_))) {
+                Self::ensure_consensus_submitter(&who)?;
+            }
 
             Self::handle_messages(messages)
         }
@@ -342,20 +588,50 @@ pub mod pallet {
             message: CreateConsensusState,
         ) -> DispatchResult {
             T::AdminOrigin::ensure_origin(origin)?;
-            let host = Host::<T>::default();
-
+            ensure!(!Paused::<T>::get(), Error::<T>::Paused);
+            ensure!(
+                Self::is_valid_consensus_client_id(message.consensus_client_id),
+                Error::<T>::ConsensusClientCreationFailed
+            );
+            let host = Host::<T>::seeding();
+
+            // guard against misconfigured genesis states, timestamps are expected in seconds
+            let valid_timestamps = message.state_machine_commitments.iter().all(|(_, state)| {
+                (primitives::MIN_INITIAL_COMMITMENT_TIMESTAMP..=
+                    primitives::MAX_INITIAL_COMMITMENT_TIMESTAMP)
+                    .contains(&state.commitment.timestamp)
+            });
+            ensure!(valid_timestamps, Error::<T>::InvalidCommitmentTimestamp);
+
+            let consensus_state_id = message.consensus_state_id;
+            let seeded_heights = message
+                .state_machine_commitments
+                .iter()
+                .map(|(id, state)| StateMachineHeight { id: *id, height: state.height })
+                .collect::<Vec<_>>();
             let result = handlers::create_client(&host, message)
                 .map_err(|_| Error::<T>::ConsensusClientCreationFailed)?;
 
+            // The first consensus state to track a state machine becomes its source of truth
+            for height in &seeded_heights {
+                if !StateMachineConsensusClient::<T>::contains_key(height.id.state_id) {
+                    StateMachineConsensusClient::<T>::insert(
+                        height.id.state_id,
+                        consensus_state_id,
+                    );
+                }
+            }
+
             Self::deposit_event(Event::<T>::ConsensusClientCreated {
                 consensus_client_id: result.consensus_client_id,
+                seeded_heights,
             });
 
             Ok(())
         }
 
         /// Set the unbonding period for a consensus state.
-        #[pallet::weight(<T as frame_system::Config>::DbWeight::get().writes(2))]
+        #[pallet::weight(<T as frame_system::Config>::DbWeight::get().writes(3))]
         #[pallet::call_index(2)]
         pub fn update_consensus_state(
             origin: OriginFor<T>,
@@ -375,6 +651,10 @@ pub mod pallet {
                     .map_err(|_| Error::<T>::UnbondingPeriodUpdateFailed)?;
             }
 
+            if let Some(min_update_interval) = message.min_update_interval {
+                MinUpdateInterval::<T>::insert(message.consensus_state_id, min_update_interval);
+            }
+
             Ok(())
         }
 
@@ -389,6 +669,227 @@ pub mod pallet {
 
             Ok(())
         }
+
+        /// Override the state machine identifier of the host. This can only be done when there
+        /// are no outgoing requests in flight, since their commitments depend on the source.
+        #[pallet::weight(<T as frame_system::Config>::DbWeight::get().reads_writes(2, 1))]
+        #[pallet::call_index(4)]
+        pub fn set_host_state_machine(
+            origin: OriginFor<T>,
+            state_machine: StateMachine,
+        ) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            ensure!(
+                RequestCommitments::<T>::iter_keys().next().is_none(),
+                Error::<T>::OutgoingRequestsInFlight
+            );
+
+            let host = Host::<T>::default();
+            let old_state_machine = host.host_state_machine();
+            HostStateMachine::<T>::put(state_machine);
+
+            Self::deposit_event(Event::<T>::HostStateMachineUpdated {
+                old_state_machine,
+                new_state_machine: state_machine,
+            });
+
+            Ok(())
+        }
+
+        /// Set the accounts allowed to submit consensus messages, an empty set allows anyone.
+        #[pallet::weight(<T as frame_system::Config>::DbWeight::get().writes(1))]
+        #[pallet::call_index(5)]
+        pub fn set_consensus_submitters(
+            origin: OriginFor<T>,
+            submitters: BTreeSet<T::AccountId>,
+        ) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            AllowedConsensusSubmitters::<T>::put(submitters);
+
+            Ok(())
+        }
+
+        /// Handles a batch of consensus messages atomically, if any of the updates fails none of
+        /// the consensus clients are updated.
+        #[pallet::weight(get_weight::<T>(&messages))]
+        #[pallet::call_index(6)]
+        #[frame_support::transactional]
+        pub fn handle_consensus_batch(
+            origin: OriginFor<T>,
+            messages: Vec<Message>,
+        ) -> DispatchResult {
+            ensure!(
+                !messages.is_empty() &&
+                    messages.iter().all(|message| matches!(message, Message::Consensus(_))),
+                Error::<T>::InvalidMessage
+            );
+            let who = ensure_signed(origin)?;
+            ensure!(!Paused::<T>::get(), Error::<T>::Paused);
+            Self::ensure_consensus_submitter(&who)?;
+
+            let errors = Self::execute_messages(messages);
+            if !errors.is_empty() {
+                debug!(target: "pallet-ismp", "Consensus batch errors {:?}", errors);
+                Err(Error::<T>::ConsensusBatchFailed)?
+            }
+
+            Ok(())
+        }
+
+        /// Handles ismp messages submitted as unsigned transactions, this must be enabled through
+        /// `T::AllowUnsignedHandle`.
+        #[pallet::weight(get_weight::<T>(&messages))]
+        #[pallet::call_index(7)]
+        #[frame_support::transactional]
+        pub fn handle_unsigned(
+            origin: OriginFor<T>,
+            messages: Vec<Message>,
+        ) -> DispatchResultWithPostInfo {
+            ensure_none(origin)?;
+            ensure!(T::AllowUnsignedHandle::get(), Error::<T>::UnsignedHandleDisabled);
+            ensure!(!Paused::<T>::get(), Error::<T>::Paused);
+            ensure!(!messages.is_empty(), Error::<T>::InvalidMessage);
+            ensure!(
+                AllowedRelayers::<T>::iter_keys().next().is_none(),
+                Error::<T>::UnauthorizedRelayer
+            );
+
+            // Unsigned consensus messages can't be attributed to an allowed submitter
+            if messages.iter().any(|message| matches!(message, Message::Consensus(_))) {
+                ensure!(
+                    AllowedConsensusSubmitters::<T>::get().is_empty(),
+                    Error::<T>::UnauthorizedConsensusSubmitter
+                );
+            }
+
+            let mut post_info = Self::handle_messages(messages)?;
+            post_info.pays_fee = Pays::No;
+            Ok(post_info)
+        }
+
+        /// Halt or resume the processing of all messages and the creation of consensus clients
+        #[pallet::weight(<T as frame_system::Config>::DbWeight::get().writes(1))]
+        #[pallet::call_index(8)]
+        pub fn set_paused(origin: OriginFor<T>, paused: bool) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            Paused::<T>::put(paused);
+
+            if paused {
+                Self::deposit_event(Event::<T>::Paused);
+            } else {
+                Self::deposit_event(Event::<T>::Unpaused);
+            }
+
+            Ok(())
+        }
+
+        /// Halt or resume the processing of messages proven against a state machine
+        #[pallet::weight(<T as frame_system::Config>::DbWeight::get().writes(1))]
+        #[pallet::call_index(9)]
+        pub fn pause_state_machine(
+            origin: OriginFor<T>,
+            state_machine_id: StateMachineId,
+            paused: bool,
+        ) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            if paused {
+                PausedStateMachines::<T>::insert(state_machine_id, true);
+            } else {
+                PausedStateMachines::<T>::remove(state_machine_id);
+            }
+
+            Ok(())
+        }
+
+        /// Halt or resume the processing of messages for a consensus client
+        #[pallet::weight(<T as frame_system::Config>::DbWeight::get().writes(1))]
+        #[pallet::call_index(10)]
+        pub fn pause_consensus_client(
+            origin: OriginFor<T>,
+            consensus_state_id: ConsensusStateId,
+            paused: bool,
+        ) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            if paused {
+                PausedConsensusClients::<T>::insert(consensus_state_id, true);
+            } else {
+                PausedConsensusClients::<T>::remove(consensus_state_id);
+            }
+
+            Ok(())
+        }
+
+        /// Allow an account to submit ISMP messages
+        #[pallet::weight(<T as frame_system::Config>::DbWeight::get().writes(1))]
+        #[pallet::call_index(11)]
+        pub fn add_relayer(origin: OriginFor<T>, relayer: T::AccountId) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            AllowedRelayers::<T>::insert(relayer, ());
+
+            Ok(())
+        }
+
+        /// Stop allowing an account to submit ISMP messages
+        #[pallet::weight(<T as frame_system::Config>::DbWeight::get().writes(1))]
+        #[pallet::call_index(12)]
+        pub fn remove_relayer(origin: OriginFor<T>, relayer: T::AccountId) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            AllowedRelayers::<T>::remove(relayer);
+
+            Ok(())
+        }
+
+        /// Set the human readable name of a consensus client
+        #[pallet::weight(<T as frame_system::Config>::DbWeight::get().writes(1))]
+        #[pallet::call_index(13)]
+        pub fn set_consensus_client_name(
+            origin: OriginFor<T>,
+            id: ConsensusClientId,
+            name: BoundedVec<u8, ConstU32<32>>,
+        ) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            ConsensusClientNames::<T>::insert(id, name);
+
+            Ok(())
+        }
+    }
+
+    #[pallet::validate_unsigned]
+    impl<T: Config> ValidateUnsigned for Pallet<T> {
+        type Call = Call<T>;
+
+        fn validate_unsigned(_source: TransactionSource, call: &Self::Call) -> TransactionValidity {
+            let messages = Self::check_unsigned(call)?;
+            Self::check_unhandled(messages)?;
+
+            // Only messages that are handled without errors are accepted into the pool, since
+            // unsigned transactions don't pay fees
+            if !Self::dry_run_messages(messages.clone()).is_empty() {
+                return InvalidTransaction::Call.into()
+            }
+
+            ValidTransaction::with_tag_prefix("ismp")
+                .and_provides(sp_io::hashing::keccak_256(&messages.encode()))
+                .longevity(T::UnsignedLongevity::get())
+                .propagate(true)
+                .build()
+        }
+
+        // The messages were dry run when they entered the pool and are handled by the call
+        // itself, so only the cheap checks are repeated when the transaction is included.
+        // Transactions overlapping one that was already included are rejected here, before their
+        // proofs are verified.
+        fn pre_dispatch(call: &Self::Call) -> Result<(), TransactionValidityError> {
+            Self::check_unhandled(Self::check_unsigned(call)?)
+        }
     }
 
     #[pallet::event]
@@ -412,6 +913,8 @@ pub mod pallet {
         ConsensusClientCreated {
             /// Consensus client id
             consensus_client_id: ConsensusClientId,
+            /// State machine heights whose commitments were seeded with the consensus client
+            seeded_heights: Vec<StateMachineHeight>,
         },
         /// An Outgoing Response has been deposited
         Response {
@@ -436,6 +939,58 @@ pub mod pallet {
             /// Message handling errors
             errors: Vec<HandlingError>,
         },
+        /// The host timestamp differs from the timestamp of a verified state commitment by more
+        /// than `T::MaxTimestampDrift`, the host's time provider might be misconfigured.
+        TimestampDriftDetected {
+            /// State machine the commitment belongs to
+            state_machine_id: StateMachineId,
+            /// Timestamp of the host in seconds
+            host_timestamp: u64,
+            /// Timestamp of the state commitment in seconds
+            state_machine_timestamp: u64,
+        },
+        /// Message processing has been halted
+        Paused,
+        /// Message processing has been resumed
+        Unpaused,
+        /// The state machine identifier of the host has been changed
+        HostStateMachineUpdated {
+            /// Previous state machine identifier
+            old_state_machine: StateMachine,
+            /// New state machine identifier
+            new_state_machine: StateMachine,
+        },
+        /// Requests and responses committed to the mmr in the finalized block
+        BlockStats {
+            /// Number of requests
+            requests: u32,
+            /// Number of responses
+            responses: u32,
+            /// Mmr root at the end of the block
+            mmr_root: H256,
+        },
+        /// A consensus client was frozen after it produced a state commitment that conflicts with
+        /// one it previously committed to
+        ConsensusClientFrozen {
+            /// The frozen consensus state
+            consensus_state_id: ConsensusStateId,
+            /// Height of the conflicting state commitment
+            height: StateMachineHeight,
+        },
+        /// The oldest pending updates of a consensus state were evicted to stay within
+        /// `MaxPendingConsensusUpdates`, their state machines won't be reported as updated
+        PendingConsensusUpdatesEvicted {
+            /// Consensus state the updates were pending for
+            consensus_state_id: ConsensusStateId,
+            /// Previous and latest heights of the evicted updates
+            evicted: Vec<(StateMachineHeight, StateMachineHeight)>,
+        },
+        /// The destination of an outgoing request has acknowledged its cancellation, the request
+        /// has been timed out to its source module
+        RequestCancelled {
+            /// Commitment of the cancelled request
+            commitment: H256,
+        },
     }
 
     /// Pallet errors
@@ -449,6 +1004,20 @@ pub mod pallet {
         UnbondingPeriodUpdateFailed,
         /// Couldn't update challenge period
         ChallengePeriodUpdateFailed,
+        /// The host state machine can't be changed while outgoing requests are in flight
+        OutgoingRequestsInFlight,
+        /// An initial state commitment has a timestamp outside the accepted range
+        InvalidCommitmentTimestamp,
+        /// The account is not allowed to submit consensus messages
+        UnauthorizedConsensusSubmitter,
+        /// A consensus message in an atomic batch failed
+        ConsensusBatchFailed,
+        /// Unsigned message submission is disabled
+        UnsignedHandleDisabled,
+        /// Message processing is paused
+        Paused,
+        /// Unsigned messages can't be submitted while relayers are restricted
+        UnauthorizedRelayer,
     }
 }
 
@@ -466,84 +1035,121 @@ impl<T: Config> Pallet<T> {
         mmr.generate_proof(leaf_indices)
     }
 
+    /// Generate an MMR proof for the given `leaf_indices` that is valid against the MMR root
+    /// committed to in the digest of the block at `block_number`.
+    /// Like [`Pallet::generate_proof`], this can only be used from an off-chain context.
+    pub fn generate_proof_at_block(
+        leaf_indices: Vec<LeafIndex>,
+        block_number: BlockNumberFor<T>,
+    ) -> Result<(Vec<Leaf>, primitives::Proof<H256>), primitives::Error> {
+        if block_number > <frame_system::Pallet<T>>::block_number() {
+            Err(primitives::Error::InvalidBestKnownBlock)?
+        }
+        // The leaf count is only recorded for blocks that modified the mmr, the root of any other
+        // block is the root of the last block before it that did
+        let mut block_number = block_number;
+        let leaves_count = loop {
+            let key = Self::leaf_count_offchain_key(block_number);
+            let leaves_count = sp_io::offchain::local_storage_get(StorageKind::PERSISTENT, &key)
+                .and_then(|elem| LeafIndex::decode(&mut &*elem).ok());
+            match leaves_count {
+                Some(leaves_count) => break leaves_count,
+                None if block_number.is_zero() => Err(primitives::Error::InvalidBestKnownBlock)?,
+                None => block_number -= One::one(),
+            }
+        };
+        let mmr = Mmr::<mmr::storage::OffchainStorage, T>::new(leaves_count);
+        mmr.generate_proof(leaf_indices)
+    }
+
+    /// Generate a single MMR proof for the outgoing requests to `dest_chain` whose nonces are in
+    /// `start_nonce..=end_nonce`.
+    /// Like [`Pallet::generate_proof`], this can only be used from an off-chain context.
+    pub fn generate_proof_for_range(
+        start_nonce: u64,
+        end_nonce: u64,
+        dest_chain: StateMachine,
+    ) -> Result<(Vec<Leaf>, primitives::Proof<H256>), primitives::Error> {
+        if start_nonce > end_nonce {
+            Err(primitives::Error::InvalidLeafIndex)?
+        }
+
+        let source_chain = Host::<T>::default().host_state_machine();
+        let leaf_indices = (start_nonce..=end_nonce)
+            .filter_map(|nonce| Self::get_leaf_index(source_chain, dest_chain, nonce, true))
+            .collect::<Vec<_>>();
+        if leaf_indices.is_empty() {
+            Err(primitives::Error::LeafNotFound)?
+        }
+
+        Self::generate_proof(leaf_indices)
+    }
+
+    /// Checks an unsigned call can be submitted without handling its messages, returning the
+    /// messages
+    fn check_unsigned(call: &Call<T>) -> Result<&Vec<Message>, TransactionValidityError> {
+        let messages = match call {
+            Call::handle_unsigned { messages } => messages,
+            _ => Err(InvalidTransaction::Call)?,
+        };
+
+        if !T::AllowUnsignedHandle::get() || Paused::<T>::get() || messages.is_empty() {
+            Err(InvalidTransaction::Call)?
+        }
+
+        // Unsigned messages can't be attributed to an allowed relayer or submitter
+        if AllowedRelayers::<T>::iter_keys().next().is_some() {
+            Err(InvalidTransaction::BadSigner)?
+        }
+
+        if messages.iter().any(|message| matches!(message, Message::Consensus(_))) &&
+            !AllowedConsensusSubmitters::<T>::get().is_empty()
+        {
+            Err(InvalidTransaction::BadSigner)?
+        }
+
+        Ok(messages)
+    }
+
+    /// Ensure none of the requests, responses or timeouts in the messages have been handled
+    fn check_unhandled(messages: &[Message]) -> Result<(), TransactionValidityError> {
+        let handled = messages.iter().any(|message| match message {
+            Message::Request(msg) => msg.requests.iter().any(|post| {
+                RequestReceipts::<T>::contains_key(hash_request::<Host<T>>(&Request::Post(
+                    post.clone(),
+                )))
+            }),
+            Message::Response(ResponseMessage::Post { responses, .. }) => {
+                responses.iter().any(|response| {
+                    ResponseReceipts::<T>::contains_key(hash_request::<Host<T>>(
+                        &response.request(),
+                    ))
+                })
+            }
+            Message::Response(ResponseMessage::Get { requests, .. }) => {
+                requests.iter().any(|request| {
+                    ResponseReceipts::<T>::contains_key(hash_request::<Host<T>>(request))
+                })
+            }
+            Message::Timeout(TimeoutMessage::Post { requests, .. }) |
+            Message::Timeout(TimeoutMessage::Get { requests }) => requests.iter().any(|request| {
+                !RequestCommitments::<T>::contains_key(hash_request::<Host<T>>(request))
+            }),
+            _ => false,
+        });
+        if handled {
+            Err(InvalidTransaction::Stale)?
+        }
+
+        Ok(())
+    }
+
     /// Provides a way to handle messages.
     pub fn handle_messages(messages: Vec<Message>) -> DispatchResultWithPostInfo {
-        // Define a host
         WeightConsumed::<T>::kill();
-        let host = Host::<T>::default();
-        let mut errors: Vec<HandlingError> = vec![];
+        ModuleGasUsed::<T>::kill();
         let total_weight = get_weight::<T>(&messages);
-        for message in messages {
-            match handle_incoming_message(&host, message.clone()) {
-                Ok(MessageResult::ConsensusMessage(res)) => {
-                    // check if this is a trusted state machine
-                    let is_trusted_state_machine = host
-                        .challenge_period(res.consensus_state_id.clone()) ==
-                        Some(Duration::from_secs(0));
-
-                    if is_trusted_state_machine {
-                        for (_, latest_height) in res.state_updates.into_iter() {
-                            Self::deposit_event(Event::<T>::StateMachineUpdated {
-                                state_machine_id: latest_height.id,
-                                latest_height: latest_height.height,
-                            })
-                        }
-                    } else {
-                        if let Some(pending_updates) =
-                            ConsensusUpdateResults::<T>::get(res.consensus_client_id)
-                        {
-                            for (_, latest_height) in pending_updates.into_iter() {
-                                Self::deposit_event(Event::<T>::StateMachineUpdated {
-                                    state_machine_id: latest_height.id,
-                                    latest_height: latest_height.height,
-                                })
-                            }
-                        }
-
-                        Self::deposit_event(Event::<T>::ChallengePeriodStarted {
-                            consensus_client_id: res.consensus_client_id,
-                            state_machines: res.state_updates.clone(),
-                        });
-
-                        // Store the new update result that have just entered the challenge
-                        // period
-                        ConsensusUpdateResults::<T>::insert(
-                            res.consensus_client_id,
-                            res.state_updates,
-                        );
-                    }
-                }
-                Ok(MessageResult::Response(res)) => {
-                    let StateMachineHeight { id, height } = match message {
-                        Message::Response(ref response) => response.proof().height.clone(),
-                        _ => unreachable!(),
-                    };
-                    // update the messaging heights
-                    if LatestMessagingHeight::<T>::get(&id) < height {
-                        LatestMessagingHeight::<T>::insert(id, height);
-                    }
-                    debug!(target: "ismp-modules", "Module Callback Results {:?}", ModuleCallbackResult::Response(res));
-                }
-                Ok(MessageResult::Request(res)) => {
-                    let StateMachineHeight { id, height } = match message {
-                        Message::Request(ref request) => request.proof.height.clone(),
-                        _ => unreachable!(),
-                    };
-                    // update the messaging heights
-                    if LatestMessagingHeight::<T>::get(&id) < height {
-                        LatestMessagingHeight::<T>::insert(id, height);
-                    }
-                    debug!(target: "ismp-modules", "Module Callback Results {:?}", ModuleCallbackResult::Request(res));
-                }
-                Ok(MessageResult::Timeout(res)) => {
-                    debug!(target: "ismp-modules", "Module Callback Results {:?}", ModuleCallbackResult::Timeout(res));
-                }
-                Err(err) => {
-                    errors.push(err.into());
-                }
-                _ => {}
-            }
-        }
+        let errors = Self::execute_messages(messages);
 
         if !errors.is_empty() {
             debug!(target: "pallet-ismp", "Handling Errors {:?}", errors);
@@ -559,6 +1165,403 @@ impl<T: Config> Pallet<T> {
         })
     }
 
+    /// Execute the messages in a storage transaction that is rolled back, returning the errors
+    /// encountered while handling them.
+    pub(crate) fn dry_run_messages(messages: Vec<Message>) -> Vec<HandlingError> {
+        sp_io::storage::start_transaction();
+        let errors = Self::execute_messages(messages);
+        sp_io::storage::rollback_transaction();
+
+        errors
+    }
+
+    /// Execute the messages, returning the errors encountered while handling them. Consensus
+    /// messages are executed first so other messages can be proven against the heights they
+    /// introduce, the relative order of messages is otherwise preserved.
+    pub(crate) fn execute_messages(messages: Vec<Message>) -> Vec<HandlingError> {
+        Self::execute_each(messages).into_iter().flatten().collect()
+    }
+
+    /// Execute the messages like [`Pallet::execute_messages`], returning the errors encountered
+    /// while handling each message in the order the messages were given.
+    fn execute_each(messages: Vec<Message>) -> Vec<Vec<HandlingError>> {
+        // Define a host
+        let host = Host::<T>::default();
+        let mut errors: Vec<Vec<HandlingError>> = messages.iter().map(|_| vec![]).collect();
+        let (consensus, others): (Vec<_>, Vec<_>) = messages
+            .into_iter()
+            .enumerate()
+            .partition(|(_, message)| matches!(message, Message::Consensus(_)));
+        for (index, message) in consensus.into_iter().chain(others) {
+            errors[index] = Self::execute_message(&host, message);
+        }
+
+        errors
+    }
+
+    /// Execute a single message, returning the errors encountered while handling it
+    fn execute_message(host: &Host<T>, message: Message) -> Vec<HandlingError> {
+        let mut errors: Vec<HandlingError> = vec![];
+        if !T::MessageFilter::filter(&message) {
+            return vec![HandlingError::FilteredMessage]
+        }
+
+        if let Err(err) = Self::check_timeout_batch_size(&message) {
+            return vec![err]
+        }
+
+        if let Err(err) = Self::check_paused_targets(&message) {
+            return vec![err]
+        }
+
+        if let Err(err) = Self::check_state_machine_consensus_client(&message) {
+            return vec![err]
+        }
+
+        if let Err(err) = Self::check_consensus_client_exists(&message) {
+            return vec![err]
+        }
+
+        if let Message::Consensus(ref msg) = message {
+            if let Err(err) = Self::check_update_interval(host, msg.consensus_state_id) {
+                return vec![err]
+            }
+        }
+
+        match handle_incoming_message(host, message.clone()) {
+            Ok(MessageResult::ConsensusMessage(res)) => {
+                // check if this is a trusted state machine
+                let is_trusted_state_machine = host
+                    .challenge_period(res.consensus_state_id.clone()) ==
+                    Some(Duration::from_secs(0));
+
+                if is_trusted_state_machine {
+                    for (_, latest_height) in res.state_updates.into_iter() {
+                        Self::check_timestamp_drift(host, &latest_height);
+                        Self::deposit_event(Event::<T>::StateMachineUpdated {
+                            state_machine_id: latest_height.id,
+                            latest_height: latest_height.height,
+                        })
+                    }
+                } else {
+                    Self::deposit_event(Event::<T>::ChallengePeriodStarted {
+                        consensus_client_id: res.consensus_client_id,
+                        state_machines: res.state_updates.clone(),
+                    });
+
+                    // Merge the new update result that have just entered the challenge
+                    // period with any updates that are still pending
+                    Self::store_pending_consensus_updates(
+                        res.consensus_state_id,
+                        res.state_updates,
+                    );
+                }
+            }
+            Ok(MessageResult::Response(res)) => {
+                let StateMachineHeight { id, height } = match message {
+                    Message::Response(ref response) => response.proof().height.clone(),
+                    _ => unreachable!(),
+                };
+                // update the messaging heights
+                if LatestMessagingHeight::<T>::get(&id) < height {
+                    LatestMessagingHeight::<T>::insert(id, height);
+                }
+                errors.extend(
+                    res.iter().filter_map(|result| result.as_ref().err()).map(HandlingError::from),
+                );
+                debug!(target: "ismp-modules", "Module Callback Results {:?}", ModuleCallbackResult::Response(res));
+            }
+            Ok(MessageResult::Request(res)) => {
+                let StateMachineHeight { id, height } = match message {
+                    Message::Request(ref request) => request.proof.height.clone(),
+                    _ => unreachable!(),
+                };
+                // update the messaging heights
+                if LatestMessagingHeight::<T>::get(&id) < height {
+                    LatestMessagingHeight::<T>::insert(id, height);
+                }
+                errors.extend(
+                    res.iter().filter_map(|result| result.as_ref().err()).map(HandlingError::from),
+                );
+                debug!(target: "ismp-modules", "Module Callback Results {:?}", ModuleCallbackResult::Request(res));
+            }
+            Ok(MessageResult::Timeout(res)) => {
+                errors.extend(
+                    res.iter().filter_map(|result| result.as_ref().err()).map(HandlingError::from),
+                );
+                debug!(target: "ismp-modules", "Module Callback Results {:?}", ModuleCallbackResult::Timeout(res));
+            }
+            Err(err) => {
+                errors.push(err.into());
+            }
+            _ => {}
+        }
+
+        errors
+    }
+
+    /// Process the messages without persisting any of their effects, returning the errors each
+    /// message would be handled with. The messages are executed exactly like a `handle` call
+    /// would, so relayers can predict which messages will fail before submitting them.
+    pub fn simulate_messages(messages: Vec<Message>) -> Vec<Result<(), Vec<HandlingError>>> {
+        sp_io::storage::start_transaction();
+        let results = Self::execute_each(messages)
+            .into_iter()
+            .map(|errors| if errors.is_empty() { Ok(()) } else { Err(errors) })
+            .collect();
+        sp_io::storage::rollback_transaction();
+
+        results
+    }
+
+    /// Ensure requests and responses are proven against the consensus state registered for
+    /// their source state machine, if any.
+    fn check_state_machine_consensus_client(message: &Message) -> Result<(), HandlingError> {
+        let id = match message {
+            Message::Request(msg) => msg.proof.height.id,
+            Message::Response(msg) => msg.proof().height.id,
+            _ => return Ok(()),
+        };
+
+        match StateMachineConsensusClient::<T>::get(id.state_id) {
+            Some(registered) if registered != id.consensus_state_id => {
+                Err(HandlingError::ConsensusClientMismatch { id, registered })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Ensure the consensus client a message is verified by has been created
+    fn check_consensus_client_exists(message: &Message) -> Result<(), HandlingError> {
+        let id = match message {
+            Message::Consensus(msg) => msg.consensus_state_id,
+            Message::Request(msg) => msg.proof.height.id.consensus_state_id,
+            Message::Response(msg) => msg.proof().height.id.consensus_state_id,
+            Message::Timeout(TimeoutMessage::Post { timeout_proof, .. }) => {
+                timeout_proof.height.id.consensus_state_id
+            }
+            _ => return Ok(()),
+        };
+
+        if !ConsensusStates::<T>::contains_key(id) {
+            Err(HandlingError::UnknownConsensusClient { id })?
+        }
+
+        Ok(())
+    }
+
+    /// Ensure timeout messages don't time out more than `T::MaxTimeoutBatchSize` requests
+    fn check_timeout_batch_size(message: &Message) -> Result<(), HandlingError> {
+        let size = match message {
+            Message::Timeout(TimeoutMessage::Post { requests, .. }) => requests.len(),
+            Message::Timeout(TimeoutMessage::Get { requests }) => requests.len(),
+            _ => return Ok(()),
+        };
+
+        let max = T::MaxTimeoutBatchSize::get();
+        if size > max as usize {
+            Err(HandlingError::BatchTooLarge { size: size as u32, max })?
+        }
+
+        Ok(())
+    }
+
+    /// Ensure the state machine and consensus client a message targets haven't been paused.
+    /// Fraud proofs are always processed.
+    fn check_paused_targets(message: &Message) -> Result<(), HandlingError> {
+        let (consensus_state_id, state_machine_id) = match message {
+            Message::Consensus(msg) => (msg.consensus_state_id, None),
+            Message::Request(msg) => {
+                (msg.proof.height.id.consensus_state_id, Some(msg.proof.height.id))
+            }
+            Message::Response(msg) => {
+                let id = msg.proof().height.id;
+                (id.consensus_state_id, Some(id))
+            }
+            Message::Timeout(TimeoutMessage::Post { timeout_proof, .. }) => {
+                let id = timeout_proof.height.id;
+                (id.consensus_state_id, Some(id))
+            }
+            _ => return Ok(()),
+        };
+
+        if PausedConsensusClients::<T>::get(consensus_state_id) {
+            Err(HandlingError::PausedConsensusClient { id: consensus_state_id })?
+        }
+
+        if let Some(id) = state_machine_id {
+            if PausedStateMachines::<T>::get(id) {
+                Err(HandlingError::PausedStateMachine { id })?
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the id isn't reserved and is known to the consensus client provider
+    fn is_valid_consensus_client_id(id: ConsensusClientId) -> bool {
+        let reserved = id == primitives::INVALID_CONSENSUS_CLIENT_ID ||
+            (!cfg!(feature = "runtime-benchmarks") &&
+                id == primitives::BENCHMARK_CONSENSUS_CLIENT_ID);
+        if reserved {
+            return false
+        }
+
+        <T as Config>::ConsensusClientProvider::consensus_client(id).is_ok()
+    }
+
+    /// Ensure the account is allowed to submit consensus messages
+    fn ensure_consensus_submitter(who: &T::AccountId) -> DispatchResult {
+        let allowed = AllowedConsensusSubmitters::<T>::get();
+        ensure!(
+            allowed.is_empty() || allowed.contains(who),
+            Error::<T>::UnauthorizedConsensusSubmitter
+        );
+        Ok(())
+    }
+
+    /// Ensure the minimum update interval configured for a consensus state, if any, has elapsed
+    /// since its last update.
+    pub(crate) fn check_update_interval(
+        host: &Host<T>,
+        consensus_state_id: ConsensusStateId,
+    ) -> Result<(), HandlingError> {
+        let (min_interval, update_time) = match (
+            MinUpdateInterval::<T>::get(consensus_state_id),
+            host.consensus_update_time(consensus_state_id),
+        ) {
+            (Some(min_interval), Ok(update_time)) => (min_interval, update_time.as_secs()),
+            _ => return Ok(()),
+        };
+        let current_time = host.timestamp().as_secs();
+
+        if current_time.saturating_sub(update_time) < min_interval {
+            Err(HandlingError::UpdateIntervalNotElapsed {
+                id: consensus_state_id,
+                update_time,
+                current_time,
+                min_interval,
+            })?
+        }
+
+        Ok(())
+    }
+
+    /// Emit a [`Event::TimestampDriftDetected`] warning if the timestamp of the state commitment
+    /// at `height` differs from the host timestamp by more than `T::MaxTimestampDrift`.
+    pub(crate) fn check_timestamp_drift(host: &Host<T>, height: &StateMachineHeight) {
+        let state_machine_timestamp = match host.state_machine_commitment(height.clone()) {
+            Ok(commitment) => commitment.timestamp,
+            Err(_) => return,
+        };
+        let host_timestamp = host.timestamp().as_secs();
+
+        if host_timestamp.abs_diff(state_machine_timestamp) > T::MaxTimestampDrift::get() {
+            log::warn!(
+                target: "pallet-ismp",
+                "Host timestamp {host_timestamp} drifts from {:?} timestamp {state_machine_timestamp}",
+                height.id
+            );
+            Self::deposit_event(Event::<T>::TimestampDriftDetected {
+                state_machine_id: height.id,
+                host_timestamp,
+                state_machine_timestamp,
+            });
+        }
+    }
+
+    /// Add state machine updates that have just entered the challenge period for a consensus
+    /// state to its pending updates, along with the time they were made, evicting the oldest
+    /// updates once `T::MaxPendingConsensusUpdates` is exceeded.
+    pub(crate) fn store_pending_consensus_updates(
+        id: ConsensusStateId,
+        updates: BTreeSet<(StateMachineHeight, StateMachineHeight)>,
+    ) {
+        // Don't leave empty entries behind for updates that didn't advance any state machine
+        if updates.is_empty() {
+            return
+        }
+
+        let now = Host::<T>::default().timestamp().as_millis().saturated_into::<u64>();
+        let max = T::MaxPendingConsensusUpdates::get() as usize;
+        let evicted = ConsensusUpdateResults::<T>::mutate(id, |pending| {
+            let pending = pending.get_or_insert_with(BTreeSet::new);
+            pending.extend(updates.into_iter().map(|(previous, latest)| (now, previous, latest)));
+            let mut evicted = vec![];
+            while pending.len() > max {
+                evicted.extend(pending.pop_first().map(|(_, previous, latest)| (previous, latest)));
+            }
+            evicted
+        });
+
+        if !evicted.is_empty() {
+            log::warn!(
+                target: "pallet-ismp",
+                "Evicted {} pending updates of consensus state {id:?}",
+                evicted.len()
+            );
+            Self::deposit_event(Event::<T>::PendingConsensusUpdatesEvicted {
+                consensus_state_id: id,
+                evicted,
+            });
+        }
+    }
+
+    /// Deposit `StateMachineUpdated` events for pending consensus updates whose challenge period
+    /// has elapsed and clear them from storage. At most `T::MaxConsensusStatesFinalizedPerBlock`
+    /// consensus states are checked per block, the next block continues where this one stopped.
+    pub(crate) fn finalize_elapsed_consensus_updates() -> Weight {
+        let host = Host::<T>::default();
+        let now = host.timestamp().as_millis().saturated_into::<u64>();
+        let limit = T::MaxConsensusStatesFinalizedPerBlock::get() as usize;
+        let pending = match ConsensusUpdateResultsCursor::<T>::get() {
+            Some(last) => ConsensusUpdateResults::<T>::iter_from(
+                ConsensusUpdateResults::<T>::hashed_key_for(last),
+            ),
+            None => ConsensusUpdateResults::<T>::iter(),
+        }
+        .take(limit)
+        .collect::<Vec<_>>();
+
+        // Start over once every consensus state has been checked
+        match pending.last() {
+            Some((id, _)) if pending.len() == limit => ConsensusUpdateResultsCursor::<T>::put(id),
+            _ => ConsensusUpdateResultsCursor::<T>::kill(),
+        }
+
+        let mut reads = 1 + pending.len() as u64;
+        let mut writes = 1u64;
+        for (id, updates) in pending {
+            reads += 1;
+            let challenge_period = match host.challenge_period(id) {
+                Some(period) => period.as_millis().saturated_into::<u64>(),
+                None => continue,
+            };
+            let (elapsed, remaining): (BTreeSet<_>, BTreeSet<_>) =
+                updates.into_iter().partition(|(update_time, _, _)| {
+                    now.saturating_sub(*update_time) >= challenge_period
+                });
+            if elapsed.is_empty() {
+                continue
+            }
+
+            for (_, _, latest_height) in elapsed {
+                Self::deposit_event(Event::<T>::StateMachineUpdated {
+                    state_machine_id: latest_height.id,
+                    latest_height: latest_height.height,
+                })
+            }
+            writes += 1;
+            if remaining.is_empty() {
+                ConsensusUpdateResults::<T>::remove(id);
+            } else {
+                ConsensusUpdateResults::<T>::insert(id, remaining);
+            }
+        }
+
+        <T as frame_system::Config>::DbWeight::get().reads_writes(reads, writes)
+    }
+
     /// Return the on-chain MMR root hash.
     pub fn mmr_root() -> H256 {
         Self::mmr_root_hash()
@@ -596,6 +1599,140 @@ impl<T: Config> Pallet<T> {
         (T::INDEXING_PREFIX, "responses_leaf_indices", source_chain, dest_chain, nonce).encode()
     }
 
+    /// Returns the offchain key for the mmr leaf count at the end of a block
+    pub fn leaf_count_offchain_key(block_number: BlockNumberFor<T>) -> Vec<u8> {
+        (T::INDEXING_PREFIX, "leaf_count", block_number).encode()
+    }
+
+    /// Returns the offchain key for the leaf index of a request or response commitment
+    pub fn commitment_leaf_index_offchain_key(commitment: H256) -> Vec<u8> {
+        (T::INDEXING_PREFIX, "commitment_leaf_index", commitment).encode()
+    }
+
+    /// Returns the offchain key for a cached state commitment
+    pub fn state_commitment_offchain_key(height: &StateMachineHeight) -> Vec<u8> {
+        (T::INDEXING_PREFIX, "state_commitment", height).encode()
+    }
+
+    /// Returns the offchain key the commitments of the GET requests dispatched in block `n` are
+    /// indexed under
+    pub fn get_requests_offchain_key(block_number: BlockNumberFor<T>) -> Vec<u8> {
+        (T::INDEXING_PREFIX, "get_requests", block_number).encode()
+    }
+
+    /// Returns the offchain key the GET requests tracked by the offchain worker are stored under
+    pub fn pending_get_requests_offchain_key() -> Vec<u8> {
+        (T::INDEXING_PREFIX, "pending_get_requests").encode()
+    }
+
+    /// Returns the heights of the destination state machine a GET request can be answered at
+    fn get_request_heights(get: &ismp_rs::router::Get) -> Vec<StateMachineHeight> {
+        LatestStateMachineHeight::<T>::iter_keys()
+            .filter(|id| id.state_id == get.dest)
+            .map(|id| StateMachineHeight { id, height: get.height })
+            .collect()
+    }
+
+    /// Track the GET requests dispatched in block `n` and cache the state commitments at the
+    /// heights they reference in the offchain storage. Cached commitments are evicted once the
+    /// requests referencing them are answered or timed out. Returns the GET requests that are
+    /// still pending.
+    fn cache_pending_state_commitments(n: BlockNumberFor<T>) -> Vec<ismp_rs::router::Get> {
+        let pending_key = Self::pending_get_requests_offchain_key();
+        let mut pending = sp_io::offchain::local_storage_get(StorageKind::PERSISTENT, &pending_key)
+            .and_then(|elem| Vec::<ismp_rs::router::Get>::decode(&mut &*elem).ok())
+            .unwrap_or_default();
+
+        let dispatched = sp_io::offchain::local_storage_get(
+            StorageKind::PERSISTENT,
+            &Self::get_requests_offchain_key(n),
+        )
+        .and_then(|elem| Vec::<H256>::decode(&mut &*elem).ok())
+        .unwrap_or_default();
+        pending.extend(dispatched.into_iter().filter_map(|commitment| {
+            let query = RequestCommitments::<T>::get(commitment)?.query;
+            let leaf_index =
+                Self::get_leaf_index(query.source_chain, query.dest_chain, query.nonce, true)?;
+            Self::get_request(leaf_index)?.get_request().ok()
+        }));
+
+        let (done, pending): (Vec<_>, Vec<_>) = pending.into_iter().partition(|get| {
+            let commitment = hash_request::<Host<T>>(&Request::Get(get.clone()));
+            ResponseReceipts::<T>::contains_key(commitment) ||
+                !RequestCommitments::<T>::contains_key(commitment)
+        });
+
+        let needed = pending.iter().flat_map(Self::get_request_heights).collect::<BTreeSet<_>>();
+        for height in done.iter().flat_map(Self::get_request_heights) {
+            if !needed.contains(&height) {
+                let key = Self::state_commitment_offchain_key(&height);
+                sp_io::offchain::local_storage_clear(StorageKind::PERSISTENT, &key);
+            }
+        }
+
+        for height in needed {
+            let key = Self::state_commitment_offchain_key(&height);
+            if sp_io::offchain::local_storage_get(StorageKind::PERSISTENT, &key).is_some() {
+                continue
+            }
+            if let Some(commitment) = StateCommitments::<T>::get(&height) {
+                sp_io::offchain::local_storage_set(
+                    StorageKind::PERSISTENT,
+                    &key,
+                    &commitment.encode(),
+                );
+            }
+        }
+
+        sp_io::offchain::local_storage_set(
+            StorageKind::PERSISTENT,
+            &pending_key,
+            &pending.encode(),
+        );
+        pending
+    }
+
+    /// Returns the offchain key the relayer hints are published under
+    pub fn relayer_hints_offchain_key() -> Vec<u8> {
+        (T::INDEXING_PREFIX, "relayer_hints").encode()
+    }
+
+    /// Publish the outgoing requests awaiting relay at block `n` to the offchain storage
+    fn publish_relayer_hints(n: BlockNumberFor<T>, pending_get_requests: &[ismp_rs::router::Get]) {
+        let hints = primitives::RelayerHints {
+            block_number: n,
+            pending_requests: OutgoingRequestsByDest::<T>::iter_keys().collect(),
+            pending_get_requests: pending_get_requests
+                .iter()
+                .map(|get| hash_request::<Host<T>>(&Request::Get(get.clone())))
+                .collect(),
+        };
+        sp_io::offchain::local_storage_set(
+            StorageKind::PERSISTENT,
+            &Self::relayer_hints_offchain_key(),
+            &hints.encode(),
+        );
+    }
+
+    /// Gets the most recently published relayer hints. Can only be used from an off-chain
+    /// context.
+    pub fn relayer_hints() -> Option<primitives::RelayerHints<BlockNumberFor<T>>> {
+        sp_io::offchain::local_storage_get(
+            StorageKind::PERSISTENT,
+            &Self::relayer_hints_offchain_key(),
+        )
+        .and_then(|elem| Decode::decode(&mut &*elem).ok())
+    }
+
+    /// Gets the state commitment at the given height, reading through the offchain cache.
+    /// Can only be used from an off-chain context.
+    pub fn cached_state_commitment(height: StateMachineHeight) -> Option<StateCommitment> {
+        let key = Self::state_commitment_offchain_key(&height);
+        sp_io::offchain::local_storage_get(StorageKind::PERSISTENT, &key)
+            .and_then(|elem| StateCommitment::decode(&mut &*elem).ok())
+            .or_else(|| StateCommitments::<T>::get(&height))
+    }
+
     /// Stores the leaf index  or the given key
     pub fn store_leaf_index_offchain(key: Vec<u8>, leaf_index: LeafIndex) {
         sp_io::offchain_index::set(&key, &leaf_index.encode());
@@ -651,33 +1788,96 @@ impl<T: Config> Pallet<T> {
         None
     }
 
+    /// Gets the leaf index for a request or response commitment from the offchain storage
+    pub fn get_commitment_leaf_index(commitment: H256) -> Option<LeafIndex> {
+        let key = Self::commitment_leaf_index_offchain_key(commitment);
+        let elem = sp_io::offchain::local_storage_get(StorageKind::PERSISTENT, &key)?;
+        LeafIndex::decode(&mut &*elem).ok()
+    }
+
+    /// Get the request with the given commitment
+    pub fn request_by_commitment(commitment: H256) -> Option<Request> {
+        Self::get_request(Self::get_commitment_leaf_index(commitment)?)
+    }
+
+    /// Get the response with the given commitment
+    pub fn response_by_commitment(commitment: H256) -> Option<Response> {
+        Self::get_response(Self::get_commitment_leaf_index(commitment)?)
+    }
+
     /// Get unfulfilled Get requests
     pub fn pending_get_requests() -> Vec<ismp_rs::router::Get> {
         RequestCommitments::<T>::iter()
-            .filter_map(|(key, query)| {
+            .filter_map(|(key, meta)| {
+                // Skip the offchain read for requests known not to be pending GET requests
+                if meta.kind == Some(primitives::RequestKind::Post) ||
+                    ResponseReceipts::<T>::contains_key(key)
+                {
+                    return None
+                }
+                let query = meta.query;
                 let leaf_index =
                     Self::get_leaf_index(query.source_chain, query.dest_chain, query.nonce, true)?;
                 let req = Self::get_request(leaf_index)?;
-                (req.is_type_get() && !ResponseReceipts::<T>::contains_key(key))
-                    .then(|| req.get_request().ok())
-                    .flatten()
+                req.is_type_get().then(|| req.get_request().ok()).flatten()
+            })
+            .collect()
+    }
+
+    /// Get the outgoing requests to `dest` that haven't been timed out
+    pub fn pending_requests_for_dest(dest: StateMachine) -> Vec<Request> {
+        OutgoingRequestsByDest::<T>::iter_key_prefix(dest)
+            .filter_map(|commitment| {
+                let query = RequestCommitments::<T>::get(commitment)?.query;
+                let leaf_index =
+                    Self::get_leaf_index(query.source_chain, query.dest_chain, query.nonce, true)?;
+                Self::get_request(leaf_index)
             })
             .collect()
     }
 
+    /// Report the weight consumed by the currently executing module callback. Callbacks that
+    /// report their consumption are charged it in place of their declared weight, so the
+    /// transaction fee is refunded the difference.
+    pub fn record_module_gas(weight: Weight) {
+        ModuleGasUsed::<T>::mutate(|used| *used = used.saturating_add(weight));
+    }
+
+    /// Returns true if the account may submit ISMP messages, anyone may if no relayers have
+    /// been allowed
+    pub fn is_allowed_relayer(who: &T::AccountId) -> bool {
+        AllowedRelayers::<T>::contains_key(who) ||
+            AllowedRelayers::<T>::iter_keys().next().is_none()
+    }
+
     /// Return the scale encoded consensus state
     pub fn get_consensus_state(id: ConsensusClientId) -> Option<Vec<u8>> {
-        ConsensusStates::<T>::get(id)
+        ConsensusStates::<T>::get(id).and_then(primitives::decode_consensus_state)
+    }
+
+    /// Return the scale encoded consensus states of the given clients, in the same order
+    pub fn get_consensus_states(ids: Vec<ConsensusClientId>) -> Vec<Option<Vec<u8>>> {
+        ids.into_iter().map(Self::get_consensus_state).collect()
+    }
+
+    /// Return the state commitments at the given heights, in the same order
+    pub fn get_state_commitments(heights: Vec<StateMachineHeight>) -> Vec<Option<StateCommitment>> {
+        Host::<T>::default().state_machine_commitments(heights)
+    }
+
+    /// Return the human readable name of a consensus client
+    pub fn get_consensus_client_name(id: ConsensusClientId) -> Option<Vec<u8>> {
+        ConsensusClientNames::<T>::get(id).map(|name| name.into_inner())
     }
 
     /// Return the timestamp this client was last updated in seconds
     pub fn get_consensus_update_time(id: ConsensusClientId) -> Option<u64> {
-        ConsensusClientUpdateTime::<T>::get(id)
+        ConsensusClientUpdateTime::<T>::get(id).map(|timestamp| timestamp / 1000)
     }
 
-    /// Return the challenge period
+    /// Return the challenge period in seconds, as resolved by the host when handling messages
     pub fn get_challenge_period(id: ConsensusClientId) -> Option<u64> {
-        ChallengePeriod::<T>::get(id)
+        Host::<T>::default().challenge_period(id).map(|period| period.as_secs())
     }
 
     /// Return latest timestamp on chain
@@ -690,6 +1890,39 @@ impl<T: Config> Pallet<T> {
         Some(LatestStateMachineHeight::<T>::get(id))
     }
 
+    /// Return the block in which the outgoing request with the given nonce was dispatched
+    pub fn get_request_block(nonce: u64) -> Option<BlockNumberFor<T>> {
+        RequestNonceToBlock::<T>::get(nonce)
+    }
+
+    /// Return the MMR root hash at the end of the given block, if it's still retained
+    pub fn get_historical_root(block_number: BlockNumberFor<T>) -> Option<H256> {
+        HistoricalRoots::<T>::get(block_number)
+    }
+
+    /// Return the positions in the MMR of the leaves pushed in the given block, if it's still
+    /// retained. These can be passed to [`Self::get_requests`] and [`Self::generate_proof`].
+    /// The list is flagged as truncated when the block pushed more than `T::MaxLeavesPerBlock`
+    /// leaves.
+    pub fn get_leaves_at_block(block_number: BlockNumberFor<T>) -> BlockLeaves {
+        BlockLeaves {
+            leaves: LeavesAtBlock::<T>::get(block_number).into_inner(),
+            truncated: TruncatedLeavesAtBlock::<T>::get(block_number),
+        }
+    }
+
+    /// Return the ids of all frozen consensus clients
+    pub fn get_frozen_consensus_clients() -> Vec<ConsensusClientId> {
+        FrozenConsensusClients::<T>::iter()
+            .filter_map(|(id, frozen)| frozen.then_some(id))
+            .collect()
+    }
+
+    /// Return all frozen state machines
+    pub fn get_frozen_state_machines() -> Vec<StateMachineId> {
+        FrozenHeights::<T>::iter_keys().collect()
+    }
+
     /// Get Request Leaf Indices
     pub fn get_request_leaf_indices(leaf_queries: Vec<LeafIndexQuery>) -> Vec<LeafIndex> {
         leaf_queries
@@ -722,22 +1955,50 @@ impl<T: Config> Pallet<T> {
 
     /// Insert a leaf into the mmr
     pub(crate) fn mmr_push(leaf: Leaf) -> Option<NodeIndex> {
-        let offchain_key = match &leaf {
-            Leaf::Request(req) => Pallet::<T>::request_leaf_index_offchain_key(
-                req.source_chain(),
-                req.dest_chain(),
-                req.nonce(),
+        let (offchain_key, commitment) = match &leaf {
+            Leaf::Request(req) => (
+                Pallet::<T>::request_leaf_index_offchain_key(
+                    req.source_chain(),
+                    req.dest_chain(),
+                    req.nonce(),
+                ),
+                hash_request::<Host<T>>(req),
             ),
-            Leaf::Response(res) => Pallet::<T>::response_leaf_index_offchain_key(
-                res.dest_chain(),
-                res.source_chain(),
-                res.nonce(),
+            Leaf::Response(res) => (
+                Pallet::<T>::response_leaf_index_offchain_key(
+                    res.dest_chain(),
+                    res.source_chain(),
+                    res.nonce(),
+                ),
+                hash_response::<Host<T>>(res),
             ),
         };
         let leaves = Self::number_of_leaves();
         let mmr: Mmr<mmr::storage::RuntimeStorage, T> = Mmr::new(leaves);
+        let is_request = matches!(leaf, Leaf::Request(_));
         let pos = mmr.push(leaf)?;
+        let block_number = <frame_system::Pallet<T>>::block_number();
+        if LeavesAtBlock::<T>::try_append(block_number, pos).is_err() {
+            TruncatedLeavesAtBlock::<T>::insert(block_number, true);
+            log::warn!(
+                target: "pallet-ismp",
+                "Leaf at {pos} was not indexed, the block already has the maximum number of leaves"
+            );
+        }
+        BlockMessageCounts::<T>::mutate(
+            |(requests, responses)| {
+                if is_request {
+                    *requests += 1
+                } else {
+                    *responses += 1
+                }
+            },
+        );
         Pallet::<T>::store_leaf_index_offchain(offchain_key, pos);
+        Pallet::<T>::store_leaf_index_offchain(
+            Pallet::<T>::commitment_leaf_index_offchain_key(commitment),
+            pos,
+        );
         Some(pos)
     }
 }
@@ -0,0 +1,182 @@
+// Copyright (C) 2023 Polytope Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Storage migrations for pallet-ismp
+
+use crate::{
+    primitives::{encode_consensus_state, RequestMetadata},
+    Call, Config, ConsensusClientUpdateTime, ConsensusStates, ConsensusUpdateResults, Pallet,
+    RequestCommitments,
+};
+use alloc::collections::BTreeSet;
+use core::marker::PhantomData;
+use frame_support::{
+    traits::{Get, GetCallIndex, GetCallName, GetStorageVersion, OnRuntimeUpgrade, StorageVersion},
+    weights::Weight,
+};
+use ismp_primitives::{call_indices, LeafIndexQuery};
+use ismp_rs::consensus::StateMachineHeight;
+use sp_std::prelude::*;
+
+/// Migrates the values of [`RequestCommitments`] from a [`LeafIndexQuery`] to a
+/// [`RequestMetadata`]. The kind and timeout of requests committed before the migration can't be
+/// recovered on-chain, so they are left unknown.
+/// This is the migration from storage version 0 to 1, it's executed by [`migrate`] and must only
+/// be executed once.
+pub struct MigrateRequestCommitments<T>(PhantomData<T>);
+
+impl<T: Config> OnRuntimeUpgrade for MigrateRequestCommitments<T> {
+    fn on_runtime_upgrade() -> Weight {
+        let mut count = 0u64;
+        RequestCommitments::<T>::translate::<LeafIndexQuery, _>(|_, query| {
+            count += 1;
+            Some(RequestMetadata { query, kind: None, timeout_timestamp: None })
+        });
+
+        <T as frame_system::Config>::DbWeight::get().reads_writes(count, count)
+    }
+}
+
+/// Migrates the values of [`ConsensusClientUpdateTime`] from seconds to milliseconds, so they
+/// can be compared with the host timestamp without losing precision.
+/// This is the migration from storage version 1 to 2, it's executed by [`migrate`] and must only
+/// be executed once.
+pub struct MigrateUpdateTimesToMillis<T>(PhantomData<T>);
+
+impl<T: Config> OnRuntimeUpgrade for MigrateUpdateTimesToMillis<T> {
+    fn on_runtime_upgrade() -> Weight {
+        let mut count = 0u64;
+        ConsensusClientUpdateTime::<T>::translate::<u64, _>(|_, secs| {
+            count += 1;
+            Some(secs.saturating_mul(1000))
+        });
+
+        <T as frame_system::Config>::DbWeight::get().reads_writes(count, count)
+    }
+}
+
+/// Prefixes every stored consensus state with the
+/// [`crate::primitives::CONSENSUS_STATE_VERSION`], consensus states stored before the migration
+/// have no version byte.
+/// This is the migration from storage version 2 to 3, it's executed by [`migrate`] and must only
+/// be executed once.
+pub struct MigrateConsensusStateVersions<T>(PhantomData<T>);
+
+impl<T: Config> OnRuntimeUpgrade for MigrateConsensusStateVersions<T> {
+    fn on_runtime_upgrade() -> Weight {
+        let mut count = 0u64;
+        ConsensusStates::<T>::translate::<Vec<u8>, _>(|_, state| {
+            count += 1;
+            Some(encode_consensus_state(state))
+        });
+
+        <T as frame_system::Config>::DbWeight::get().reads_writes(count, count)
+    }
+}
+
+/// Adds the time each pending consensus update was made to [`ConsensusUpdateResults`], so
+/// updates are finalized once their own challenge period has elapsed. The pending updates of a
+/// consensus state are assumed to have been made at its last update.
+/// This is the migration from storage version 3 to 4, it's executed by [`migrate`] and must only
+/// be executed once.
+pub struct MigrateConsensusUpdateTimes<T>(PhantomData<T>);
+
+impl<T: Config> OnRuntimeUpgrade for MigrateConsensusUpdateTimes<T> {
+    fn on_runtime_upgrade() -> Weight {
+        let mut count = 0u64;
+        ConsensusUpdateResults::<T>::translate::<
+            BTreeSet<(StateMachineHeight, StateMachineHeight)>,
+            _,
+        >(|id, updates| {
+            count += 1;
+            let update_time = ConsensusClientUpdateTime::<T>::get(id).unwrap_or_default();
+            Some(
+                updates
+                    .into_iter()
+                    .map(|(previous, latest)| (update_time, previous, latest))
+                    .collect(),
+            )
+        });
+
+        <T as frame_system::Config>::DbWeight::get().reads_writes(count * 2, count)
+    }
+}
+
+/// Runs every migration between the on-chain storage version and the current storage version
+/// in order, bumping the on-chain version after each one.
+pub fn migrate<T: Config>() -> Weight {
+    let db_weight = <T as frame_system::Config>::DbWeight::get();
+    let mut weight = db_weight.reads(1);
+    let on_chain = Pallet::<T>::on_chain_storage_version();
+
+    if !call_indices_unchanged::<T>() {
+        log::error!(target: "pallet-ismp", "pallet-ismp call indices have changed");
+    }
+
+    if on_chain < 1 {
+        log::info!(target: "pallet-ismp", "Migrating pallet-ismp storage to v1");
+        weight = weight.saturating_add(MigrateRequestCommitments::<T>::on_runtime_upgrade());
+        StorageVersion::new(1).put::<Pallet<T>>();
+        weight = weight.saturating_add(db_weight.writes(1));
+    }
+
+    if on_chain < 2 {
+        log::info!(target: "pallet-ismp", "Migrating pallet-ismp storage to v2");
+        weight = weight.saturating_add(MigrateUpdateTimesToMillis::<T>::on_runtime_upgrade());
+        StorageVersion::new(2).put::<Pallet<T>>();
+        weight = weight.saturating_add(db_weight.writes(1));
+    }
+
+    if on_chain < 3 {
+        log::info!(target: "pallet-ismp", "Migrating pallet-ismp storage to v3");
+        weight = weight.saturating_add(MigrateConsensusStateVersions::<T>::on_runtime_upgrade());
+        StorageVersion::new(3).put::<Pallet<T>>();
+        weight = weight.saturating_add(db_weight.writes(1));
+    }
+
+    if on_chain < 4 {
+        log::info!(target: "pallet-ismp", "Migrating pallet-ismp storage to v4");
+        weight = weight.saturating_add(MigrateConsensusUpdateTimes::<T>::on_runtime_upgrade());
+        StorageVersion::new(4).put::<Pallet<T>>();
+        weight = weight.saturating_add(db_weight.writes(1));
+    }
+
+    weight
+}
+
+/// Returns true if the call indices of the pallet still match the published [`call_indices`]
+pub fn call_indices_unchanged<T: Config>() -> bool {
+    let expected = [
+        ("handle", call_indices::HANDLE),
+        ("create_consensus_client", call_indices::CREATE_CONSENSUS_CLIENT),
+        ("update_consensus_state", call_indices::UPDATE_CONSENSUS_STATE),
+        ("set_config", call_indices::SET_CONFIG),
+        ("set_host_state_machine", call_indices::SET_HOST_STATE_MACHINE),
+        ("set_consensus_submitters", call_indices::SET_CONSENSUS_SUBMITTERS),
+        ("handle_consensus_batch", call_indices::HANDLE_CONSENSUS_BATCH),
+        ("handle_unsigned", call_indices::HANDLE_UNSIGNED),
+        ("set_paused", call_indices::SET_PAUSED),
+        ("pause_state_machine", call_indices::PAUSE_STATE_MACHINE),
+        ("pause_consensus_client", call_indices::PAUSE_CONSENSUS_CLIENT),
+        ("add_relayer", call_indices::ADD_RELAYER),
+        ("remove_relayer", call_indices::REMOVE_RELAYER),
+        ("set_consensus_client_name", call_indices::SET_CONSENSUS_CLIENT_NAME),
+    ];
+    let names = Call::<T>::get_call_names();
+    let indices = Call::<T>::get_call_indices();
+    expected
+        .iter()
+        .all(|(name, index)| names.iter().zip(indices).any(|(n, i)| n == name && i == index))
+}
@@ -88,6 +88,18 @@ where
         positions: Vec<NodeIndex>,
     ) -> Result<(Vec<Leaf>, Proof<H256>), Error> {
         let store = <Storage<OffchainStorage, T>>::default();
+        // The offchain db must contain every node committed to by the on-chain leaf count,
+        // otherwise the generated proof will not verify against the on-chain root.
+        let size = NodesUtils::new(self.leaves).size();
+        if size > 0 && !matches!(mmr_lib::MMRStore::get_elem(&store, size - 1), Ok(Some(_))) {
+            log::error!(
+                target: "runtime::mmr",
+                "Offchain db does not contain all {} mmr leaves, it should be re-synced from a node with offchain indexing enabled",
+                self.leaves
+            );
+            Err(Error::InconsistentOffchainStorage)?
+        }
+
         let leaves = positions
             .iter()
             .map(|pos| match mmr_lib::MMRStore::get_elem(&store, *pos) {
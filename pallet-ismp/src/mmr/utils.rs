@@ -14,6 +14,7 @@
 // limitations under the License.
 
 use ismp_primitives::mmr::{LeafIndex, NodeIndex};
+use sp_std::prelude::*;
 
 /// MMR nodes & size -related utilities.
 pub struct NodesUtils {
@@ -40,4 +41,13 @@ impl NodesUtils {
     pub fn size(&self) -> NodeIndex {
         2 * self.no_of_leaves - self.number_of_peaks()
     }
+
+    /// Calculate the node indices of all the peaks in the MMR, in ascending order.
+    pub fn peak_positions(&self) -> Vec<NodeIndex> {
+        if self.no_of_leaves == 0 {
+            return vec![]
+        }
+
+        mmr_lib::helper::get_peaks(self.size())
+    }
 }
@@ -1,6 +1,7 @@
 //! Mocks used by both tests and benchmarks
 use crate::primitives::ModuleId;
 use alloc::collections::BTreeMap;
+use codec::{Decode, Encode};
 use frame_support::PalletId;
 use ismp_rs::{
     consensus::{
@@ -21,6 +22,9 @@ pub const MOCK_CONSENSUS_STATE_ID: [u8; 4] = *b"mock";
 /// module id for the mock benchmarking module
 pub const MODULE_ID: ModuleId = ModuleId::Pallet(PalletId(*b"__mock__"));
 
+/// Data of post requests the mock module rejects
+pub const REJECTED_REQUEST_DATA: &[u8] = b"reject";
+
 fn set_timestamp<T: pallet_timestamp::Config>(value: u64)
 where
     <T as pallet_timestamp::Config>::Moment: From<u64>,
@@ -33,19 +37,39 @@ where
 pub struct MockModule;
 
 impl IsmpModule for MockModule {
-    fn on_accept(&self, _request: Post) -> Result<(), ismp_rs::error::Error> {
+    fn on_accept(&self, request: Post) -> Result<(), ismp_rs::error::Error> {
+        if request.data == REJECTED_REQUEST_DATA {
+            Err(IsmpError::ImplementationSpecific("Request rejected".into()))?
+        }
         Ok(())
     }
 
-    fn on_response(&self, _response: Response) -> Result<(), ismp_rs::error::Error> {
+    fn on_response(&self, response: Response) -> Result<(), ismp_rs::error::Error> {
+        // Record the values of get responses so tests can inspect them
+        if let Response::Get(response) = response {
+            let key = get_response_key(&Request::Get(response.get));
+            frame_support::storage::unhashed::put(&key, &response.values);
+        }
         Ok(())
     }
 
-    fn on_timeout(&self, _request: Request) -> Result<(), ismp_rs::error::Error> {
+    fn on_timeout(&self, request: Request) -> Result<(), ismp_rs::error::Error> {
+        // Record the timeout so tests can check that the module was refunded
+        frame_support::storage::unhashed::put(&timeout_key(&request), &true);
         Ok(())
     }
 }
 
+/// Storage key under which the [`MockModule`] records the values of a get response
+pub fn get_response_key(request: &Request) -> Vec<u8> {
+    (b"mock_get_response", request.source_chain(), request.nonce()).encode()
+}
+
+/// Storage key under which the [`MockModule`] records a timed out request
+pub fn timeout_key(request: &Request) -> Vec<u8> {
+    (b"mock_timeout", request.source_chain(), request.nonce()).encode()
+}
+
 /// A mock consensus client for benchmarking
 #[derive(Default)]
 pub struct MockConsensusClient;
@@ -56,9 +80,16 @@ impl ConsensusClient for MockConsensusClient {
         _host: &dyn IsmpHost,
         _cs_id: ismp_rs::consensus::ConsensusStateId,
         _trusted_consensus_state: Vec<u8>,
-        _proof: Vec<u8>,
+        proof: Vec<u8>,
     ) -> Result<(Vec<u8>, VerifiedCommitments), IsmpError> {
-        Ok(Default::default())
+        // A non-empty proof is the scale encoded commitments it verifies
+        if proof.is_empty() {
+            return Ok(Default::default())
+        }
+
+        let commitments = VerifiedCommitments::decode(&mut &proof[..])
+            .map_err(|_| IsmpError::ImplementationSpecific("Invalid mock proof".into()))?;
+        Ok((vec![], commitments))
     }
 
     fn verify_fraud_proof(
@@ -90,18 +121,24 @@ impl StateMachineClient for MockStateMachine {
         Ok(())
     }
 
-    fn state_trie_key(&self, _request: Vec<Request>) -> Vec<Vec<u8>> {
-        Default::default()
+    fn state_trie_key(&self, requests: Vec<Request>) -> Vec<Vec<u8>> {
+        requests.into_iter().map(|request| request.encode()).collect()
     }
 
+    /// An empty proof is treated as a non-membership proof for all keys, any other proof proves
+    /// that every key is present with the proof bytes as its value.
     fn verify_state_proof(
         &self,
         _host: &dyn IsmpHost,
-        _keys: Vec<Vec<u8>>,
+        keys: Vec<Vec<u8>>,
         _root: StateCommitment,
-        _proof: &Proof,
+        proof: &Proof,
     ) -> Result<BTreeMap<Vec<u8>, Option<Vec<u8>>>, IsmpError> {
-        Ok(Default::default())
+        if proof.proof.is_empty() {
+            return Ok(Default::default())
+        }
+
+        Ok(keys.into_iter().map(|key| (key, Some(proof.proof.clone()))).collect())
     }
 }
 
@@ -149,3 +186,56 @@ where
     set_timestamp::<T>(1000_000_000);
     height
 }
+
+/// Storage key under which the [`MockXcmExecutor`] records the last executed XCM message
+#[cfg(feature = "xcm")]
+pub const XCM_EXECUTED_KEY: &[u8] = b"mock_xcm_executed";
+
+/// XCM module executing messages with the [`MockXcmExecutor`]
+#[cfg(feature = "xcm")]
+pub type MockXcmModule = crate::xcm_bridge::XcmIsmpModule<
+    MockXcmExecutor,
+    (),
+    frame_support::traits::ConstU64<1_000_000>,
+>;
+
+/// XCM executor that records the origin and message of every executed XCM message
+#[cfg(feature = "xcm")]
+pub struct MockXcmExecutor;
+
+/// An XCM message prepared by the [`MockXcmExecutor`]
+#[cfg(feature = "xcm")]
+pub struct MockPreparedXcm(xcm::latest::Xcm<()>);
+
+#[cfg(feature = "xcm")]
+impl xcm::latest::PreparedMessage for MockPreparedXcm {
+    fn weight_of(&self) -> frame_support::weights::Weight {
+        Default::default()
+    }
+}
+
+#[cfg(feature = "xcm")]
+impl xcm::latest::ExecuteXcm<()> for MockXcmExecutor {
+    type Prepared = MockPreparedXcm;
+
+    fn prepare(message: xcm::latest::Xcm<()>) -> Result<Self::Prepared, xcm::latest::Xcm<()>> {
+        Ok(MockPreparedXcm(message))
+    }
+
+    fn execute(
+        origin: impl Into<xcm::latest::MultiLocation>,
+        pre: Self::Prepared,
+        _id: &mut xcm::latest::XcmHash,
+        _weight_credit: frame_support::weights::Weight,
+    ) -> xcm::latest::Outcome {
+        frame_support::storage::unhashed::put(XCM_EXECUTED_KEY, &(origin.into(), pre.0));
+        xcm::latest::Outcome::Complete(Default::default())
+    }
+
+    fn charge_fees(
+        _location: impl Into<xcm::latest::MultiLocation>,
+        _fees: xcm::latest::MultiAssets,
+    ) -> xcm::latest::XcmResult {
+        Ok(())
+    }
+}
@@ -20,10 +20,21 @@ pub mod ismp;
 use crate as pallet_ismp;
 use crate::*;
 
-use crate::primitives::ConsensusClientProvider;
-use frame_support::traits::{ConstU32, ConstU64, Get};
+use crate::{
+    primitives::{ConsensusClientProvider, ModuleId},
+    weight_info::{ConsensusClientWeight, IsmpModuleWeight, WeightProvider},
+};
+use frame_support::{
+    traits::{ConstBool, ConstU32, ConstU64, Get},
+    weights::Weight,
+    PalletId,
+};
 use frame_system::EnsureRoot;
-use ismp_rs::{consensus::ConsensusClient, module::IsmpModule, router::IsmpRouter};
+use ismp_rs::{
+    consensus::ConsensusClient,
+    module::IsmpModule,
+    router::{IsmpRouter, Post, Request, Response},
+};
 
 use ismp::{MockConsensusClient, MockModule};
 use sp_core::H256;
@@ -39,7 +50,7 @@ frame_support::construct_runtime!(
     pub enum Test {
         System: frame_system::{Pallet, Call, Config<T>, Storage, Event<T>},
         Timestamp: pallet_timestamp::{Pallet, Call, Storage, Inherent},
-        Ismp: pallet_ismp::{Pallet, Storage, Call, Event<T>},
+        Ismp: pallet_ismp::{Pallet, Storage, Call, Event<T>, ValidateUnsigned},
     }
 );
 
@@ -103,14 +114,136 @@ impl Config for Test {
     type IsmpRouter = ModuleRouter;
     type ConsensusClientProvider = ConsensusProvider;
     type WeightInfo = ();
-    type WeightProvider = ();
+    type WeightProvider = MockWeightProvider;
+    type MaxPendingConsensusUpdates = ConstU32<5>;
+    type MaxConsensusStatesFinalizedPerBlock = ConstU32<16>;
+    type MaxGetResponseValues = ConstU32<32>;
+    type MaxGetResponseValueSize = ConstU32<64>;
+    type MaxConsensusStateBytes = ConstU32<1024>;
+    type MaxTimeoutBatchSize = ConstU32<8>;
+    type MaxTimestampDrift = ConstU64<{ 60 * 60 }>;
+    type AllowUnsignedHandle = ConstBool<true>;
+    type UnsignedLongevity = ConstU64<25>;
+    type PublishRelayerHints = ConstBool<true>;
+    type RootHistoryDepth = ConstU64<10>;
+    type MaxLeavesPerBlock = ConstU32<4>;
+    type MessageFilter = MockMessageFilter;
+}
+
+/// Storage key of the flag that makes the [`MockMessageFilter`] reject consensus messages
+pub const FILTER_CONSENSUS_KEY: &[u8] = b"mock_filter_consensus";
+
+/// Accepts all messages unless [`FILTER_CONSENSUS_KEY`] is set
+pub struct MockMessageFilter;
+
+impl FilterMessage for MockMessageFilter {
+    fn filter(message: &Message) -> bool {
+        if frame_support::storage::unhashed::get_or_default::<bool>(FILTER_CONSENSUS_KEY) {
+            return AcceptNoConsensus::filter(message)
+        }
+        true
+    }
 }
 
 #[derive(Default)]
 pub struct ModuleRouter;
 
 impl IsmpRouter for ModuleRouter {
-    fn module_for_id(&self, _bytes: Vec<u8>) -> Result<Box<dyn IsmpModule>, ismp_rs::error::Error> {
+    fn module_for_id(&self, bytes: Vec<u8>) -> Result<Box<dyn IsmpModule>, ismp_rs::error::Error> {
+        if bytes == CALLBACK_MODULE_ID.to_bytes() {
+            return Ok(Box::new(CallbackModule))
+        }
+        if bytes == METERED_MODULE_ID.to_bytes() {
+            return Ok(Box::new(MeteredModule))
+        }
+        #[cfg(feature = "xcm")]
+        if bytes == crate::xcm_bridge::XCM_MODULE_ID.0.to_vec() {
+            return Ok(Box::new(ismp::MockXcmModule::default()))
+        }
         Ok(Box::new(MockModule))
     }
 }
+
+/// Module whose callbacks are metered like contract callbacks
+pub const CALLBACK_MODULE_ID: ModuleId = ModuleId::Pallet(PalletId(*b"callback"));
+
+/// Weight declared for each [`CallbackModule`] callback
+pub const CALLBACK_WEIGHT_LIMIT: Weight = Weight::from_parts(1_000_000, 0);
+
+/// Weight actually consumed by each [`CallbackModule`] callback
+pub const CALLBACK_WEIGHT_USED: Weight = Weight::from_parts(400_000, 0);
+
+/// A module that records the weight used by its callbacks in [`WeightConsumed`]
+pub struct CallbackModule;
+
+impl IsmpModule for CallbackModule {
+    fn on_accept(&self, _request: Post) -> Result<(), ismp_rs::error::Error> {
+        WeightConsumed::<Test>::mutate(|weight| {
+            weight.weight_used += CALLBACK_WEIGHT_USED;
+            weight.weight_limit += CALLBACK_WEIGHT_LIMIT;
+        });
+        Ok(())
+    }
+
+    fn on_response(&self, _response: Response) -> Result<(), ismp_rs::error::Error> {
+        Ok(())
+    }
+
+    fn on_timeout(&self, _request: Request) -> Result<(), ismp_rs::error::Error> {
+        Ok(())
+    }
+}
+
+/// Module that reports the weight used by its callbacks through [`Pallet::record_module_gas`]
+pub const METERED_MODULE_ID: ModuleId = ModuleId::Pallet(PalletId(*b"gasmeter"));
+
+/// A module that reports [`CALLBACK_WEIGHT_USED`] for each callback
+pub struct MeteredModule;
+
+impl IsmpModule for MeteredModule {
+    fn on_accept(&self, _request: Post) -> Result<(), ismp_rs::error::Error> {
+        Ismp::record_module_gas(CALLBACK_WEIGHT_USED);
+        Ok(())
+    }
+
+    fn on_response(&self, _response: Response) -> Result<(), ismp_rs::error::Error> {
+        Ismp::record_module_gas(CALLBACK_WEIGHT_USED);
+        Ok(())
+    }
+
+    fn on_timeout(&self, _request: Request) -> Result<(), ismp_rs::error::Error> {
+        Ismp::record_module_gas(CALLBACK_WEIGHT_USED);
+        Ok(())
+    }
+}
+
+/// Declares [`CALLBACK_WEIGHT_LIMIT`] for the callbacks of the [`CallbackModule`] and the
+/// [`MeteredModule`]
+pub struct CallbackModuleWeight;
+
+impl IsmpModuleWeight for CallbackModuleWeight {
+    fn on_accept(&self, _request: &Post) -> Weight {
+        CALLBACK_WEIGHT_LIMIT
+    }
+
+    fn on_timeout(&self, _request: &Request) -> Weight {
+        CALLBACK_WEIGHT_LIMIT
+    }
+
+    fn on_response(&self, _response: &Response) -> Weight {
+        CALLBACK_WEIGHT_LIMIT
+    }
+}
+
+pub struct MockWeightProvider;
+
+impl WeightProvider for MockWeightProvider {
+    fn consensus_client(_id: ConsensusClientId) -> Option<Box<dyn ConsensusClientWeight>> {
+        None
+    }
+
+    fn module_callback(dest_module: ModuleId) -> Option<Box<dyn IsmpModuleWeight>> {
+        (dest_module == CALLBACK_MODULE_ID || dest_module == METERED_MODULE_ID)
+            .then(|| Box::new(CallbackModuleWeight) as Box<dyn IsmpModuleWeight>)
+    }
+}
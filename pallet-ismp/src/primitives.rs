@@ -14,14 +14,24 @@
 // limitations under the License.
 
 //! Pallet primitives
-use codec::{Decode, Encode};
-use frame_support::{weights::Weight, PalletId};
-use ismp_primitives::mmr::{LeafIndex, NodeIndex};
-use ismp_rs::consensus::{ConsensusClient, ConsensusClientId};
+use crate::NodesUtils;
+use alloc::string::{String, ToString};
+use codec::{Decode, DecodeAll, Encode};
+use frame_support::{weights::Weight, PalletId, StorageHasher};
+use ismp_primitives::{
+    mmr::{LeafIndex, NodeIndex},
+    LeafIndexQuery,
+};
+use ismp_rs::{
+    consensus::{ConsensusClient, ConsensusClientId},
+    host::StateMachine,
+    messaging::Message,
+    router::{DispatchPost, Post, Request},
+};
 use scale_info::TypeInfo;
 use sp_core::{
     crypto::{AccountId32, ByteArray},
-    H160,
+    H160, H256,
 };
 use sp_runtime::RuntimeDebug;
 use sp_std::prelude::*;
@@ -37,6 +47,71 @@ pub struct Proof<Hash> {
     pub items: Vec<Hash>,
 }
 
+impl<Hash> Proof<Hash> {
+    /// Cheaply check that the proof is well formed before verifying it: it must be for at least
+    /// one leaf, its leaf positions must be unique and within the mmr, and it can't contain more
+    /// items than the leaves could need.
+    pub fn verify_shape(&self) -> Result<(), Error> {
+        let mmr_size = NodesUtils::new(self.leaf_count).size();
+        let mut positions = self.leaf_indices.clone();
+        positions.sort_unstable();
+        positions.dedup();
+        if positions.is_empty() ||
+            positions.len() != self.leaf_indices.len() ||
+            positions.iter().any(|pos| *pos >= mmr_size)
+        {
+            Err(Error::InvalidLeafIndex)?
+        }
+
+        // every leaf needs at most one sibling per level of the tallest tree, the peaks are
+        // bounded by the same number
+        let height = (u64::BITS - self.leaf_count.leading_zeros()) as usize;
+        if self.items.len() > (self.leaf_indices.len() + 1) * height {
+            Err(Error::InvalidProofShape)?
+        }
+
+        Ok(())
+    }
+}
+
+/// The kind of an outgoing request
+#[derive(codec::Encode, codec::Decode, RuntimeDebug, Clone, Copy, PartialEq, Eq, TypeInfo)]
+pub enum RequestKind {
+    /// A POST request
+    Post,
+    /// A GET request
+    Get,
+}
+
+/// The value stored for outgoing request commitments
+#[derive(codec::Encode, codec::Decode, RuntimeDebug, Clone, PartialEq, Eq, TypeInfo)]
+pub struct RequestMetadata {
+    /// Query for the request leaf in the mmr
+    pub query: LeafIndexQuery,
+    /// The kind of request, unknown for requests committed before it was recorded
+    pub kind: Option<RequestKind>,
+    /// The request timeout timestamp, unknown for requests committed before it was recorded
+    pub timeout_timestamp: Option<u64>,
+}
+
+impl From<&Request> for RequestMetadata {
+    fn from(request: &Request) -> Self {
+        let (kind, timeout_timestamp) = match request {
+            Request::Post(post) => (RequestKind::Post, post.timeout_timestamp),
+            Request::Get(get) => (RequestKind::Get, get.timeout_timestamp),
+        };
+        Self {
+            query: LeafIndexQuery {
+                source_chain: request.source_chain(),
+                dest_chain: request.dest_chain(),
+                nonce: request.nonce(),
+            },
+            kind: Some(kind),
+            timeout_timestamp: Some(timeout_timestamp),
+        }
+    }
+}
+
 /// Merkle Mountain Range operation error.
 #[derive(RuntimeDebug, codec::Encode, codec::Decode, PartialEq, Eq, scale_info::TypeInfo)]
 #[allow(missing_docs)]
@@ -51,8 +126,56 @@ pub enum Error {
     PalletNotIncluded,
     InvalidLeafIndex,
     InvalidBestKnownBlock,
+    InconsistentOffchainStorage,
+    InvalidProofShape,
 }
 
+/// Version of the encoding used for consensus states in storage, it is stored as the first byte
+/// of every consensus state.
+pub const CONSENSUS_STATE_VERSION: u8 = 1;
+
+/// Prefix a consensus state with the current [`CONSENSUS_STATE_VERSION`] for storage
+pub fn encode_consensus_state(state: Vec<u8>) -> Vec<u8> {
+    let mut versioned = Vec::with_capacity(state.len() + 1);
+    versioned.push(CONSENSUS_STATE_VERSION);
+    versioned.extend(state);
+    versioned
+}
+
+/// Strip the version byte from a stored consensus state, returns `None` if the state wasn't
+/// stored with the current [`CONSENSUS_STATE_VERSION`].
+///
+/// There is no fallback for legacy unversioned states: their first byte is arbitrary, so a legacy
+/// state starting with the version byte can't be told apart from a versioned one, and any other
+/// first byte is indistinguishable from a future version. Instead
+/// [`crate::migrations::MigrateConsensusStateVersions`] prefixes every state stored before storage
+/// version 3 in the same upgrade that introduces the prefix, so no unversioned state is left in
+/// storage for this function to misread.
+pub fn decode_consensus_state(mut stored: Vec<u8>) -> Option<Vec<u8>> {
+    match stored.first() {
+        Some(&CONSENSUS_STATE_VERSION) => {
+            stored.remove(0);
+            Some(stored)
+        }
+        _ => None,
+    }
+}
+
+/// Consensus client id that is never valid
+pub const INVALID_CONSENSUS_CLIENT_ID: ConsensusClientId = [0u8; 4];
+
+/// Consensus client id reserved for the benchmarking consensus client, it can only be used in
+/// runtime-benchmarks builds
+pub const BENCHMARK_CONSENSUS_CLIENT_ID: ConsensusClientId = [1u8; 4];
+
+/// Earliest timestamp in seconds accepted for the initial state commitments of a new consensus
+/// client, 2020-01-01T00:00:00Z
+pub const MIN_INITIAL_COMMITMENT_TIMESTAMP: u64 = 1_577_836_800;
+
+/// Latest timestamp in seconds accepted for the initial state commitments of a new consensus
+/// client, 2100-01-01T00:00:00Z
+pub const MAX_INITIAL_COMMITMENT_TIMESTAMP: u64 = 4_102_444_800;
+
 /// A trait that returns a reference to a consensus client based on its Id
 /// This trait should be implemented in the runtime
 pub trait ConsensusClientProvider {
@@ -62,6 +185,62 @@ pub trait ConsensusClientProvider {
     ) -> Result<Box<dyn ConsensusClient>, ismp_rs::error::Error>;
 }
 
+macro_rules! impl_consensus_client_provider_for_tuples {
+    ($($provider:ident),+) => {
+        /// Tries each provider in order, returning the first consensus client found
+        impl<$($provider: ConsensusClientProvider),+> ConsensusClientProvider for ($($provider,)+) {
+            fn consensus_client(
+                id: ConsensusClientId,
+            ) -> Result<Box<dyn ConsensusClient>, ismp_rs::error::Error> {
+                let mut result = Err(ismp_rs::error::Error::ImplementationSpecific(
+                    "No consensus client provider".to_string(),
+                ));
+                $(
+                    if result.is_err() {
+                        result = $provider::consensus_client(id);
+                    }
+                )+
+                result
+            }
+        }
+    };
+}
+
+impl_consensus_client_provider_for_tuples!(A);
+impl_consensus_client_provider_for_tuples!(A, B);
+impl_consensus_client_provider_for_tuples!(A, B, C);
+impl_consensus_client_provider_for_tuples!(A, B, C, D);
+impl_consensus_client_provider_for_tuples!(A, B, C, D, E);
+impl_consensus_client_provider_for_tuples!(A, B, C, D, E, F);
+impl_consensus_client_provider_for_tuples!(A, B, C, D, E, F, G);
+impl_consensus_client_provider_for_tuples!(A, B, C, D, E, F, G, H);
+
+/// Decides which messages are accepted by [`crate::Pallet::handle`] and the other message
+/// handling calls
+pub trait FilterMessage {
+    /// Returns true if the message should be processed
+    fn filter(message: &Message) -> bool;
+}
+
+/// Accepts every message
+pub struct AcceptAll;
+
+impl FilterMessage for AcceptAll {
+    fn filter(_message: &Message) -> bool {
+        true
+    }
+}
+
+/// Rejects consensus messages, for chains whose consensus clients are only updated through
+/// governance
+pub struct AcceptNoConsensus;
+
+impl FilterMessage for AcceptNoConsensus {
+    fn filter(message: &Message) -> bool {
+        !matches!(message, Message::Consensus(_))
+    }
+}
+
 /// Module identification types supported by ismp
 #[derive(PartialEq, Eq, scale_info::TypeInfo)]
 pub enum ModuleId {
@@ -84,7 +263,7 @@ impl ModuleId {
     }
 
     /// Derive module id from raw bytes
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ModuleIdError> {
         if bytes.len() == 8 {
             let mut inner = [0u8; 8];
             inner.copy_from_slice(bytes);
@@ -94,11 +273,109 @@ impl ModuleId {
         } else if bytes.len() == 20 {
             Ok(Self::Evm(H160::from_slice(bytes)))
         } else {
-            Err("Unknown Module ID format")
+            Err(ModuleIdError::InvalidLength(bytes.len()))
+        }
+    }
+}
+
+/// Error decoding a [`ModuleId`] from raw bytes
+#[derive(RuntimeDebug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleIdError {
+    /// The length of the id doesn't match any supported format
+    InvalidLength(usize),
+}
+
+impl core::fmt::Display for ModuleIdError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ModuleIdError::InvalidLength(len) => write!(
+                f,
+                "Unknown module id format of {len} bytes, expected a pallet id (8 bytes), an evm \
+                 address (20 bytes) or a contract account (32 bytes)"
+            ),
         }
     }
 }
 
+impl From<ModuleIdError> for ismp_rs::error::Error {
+    fn from(err: ModuleIdError) -> Self {
+        ismp_rs::error::Error::ImplementationSpecific(alloc::format!("{err}"))
+    }
+}
+
+/// Reason an outgoing request or response couldn't be dispatched
+#[derive(RuntimeDebug, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchErrorKind {
+    /// The request or response has already been dispatched
+    Duplicate,
+    /// The request or response couldn't be pushed into the mmr
+    MmrPushFailed,
+    /// The request is addressed to the host state machine
+    DestinationIsHost,
+    /// The response is for a request that hasn't been received, or the cancelled request hasn't
+    /// been dispatched
+    UnknownRequest,
+    /// The request is sent from or addressed to a module id reserved by the pallet
+    ReservedModule,
+    /// The self GET request isn't addressed to the host state machine
+    DestinationNotHost,
+    /// The response to a self GET request exceeds `MaxGetResponseValues` or
+    /// `MaxGetResponseValueSize`
+    ResponseTooLarge,
+    /// The sending module couldn't be found or rejected the response to its self GET request
+    ModuleCallbackFailed,
+}
+
+/// Error dispatching an outgoing request or response
+#[derive(RuntimeDebug, Clone, PartialEq, Eq)]
+pub struct DispatchFailure {
+    /// Kind of the failure
+    pub kind: DispatchErrorKind,
+    /// Description of the failure
+    pub msg: String,
+}
+
+impl DispatchFailure {
+    /// Create a dispatch failure of the given kind
+    pub fn new(kind: DispatchErrorKind, msg: impl Into<String>) -> Self {
+        Self { kind, msg: msg.into() }
+    }
+}
+
+impl core::fmt::Display for DispatchFailure {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl From<DispatchFailure> for ismp_rs::error::Error {
+    fn from(err: DispatchFailure) -> Self {
+        ismp_rs::error::Error::ImplementationSpecific(err.msg)
+    }
+}
+
+/// Positions in the MMR of the leaves pushed in a block
+#[derive(Encode, Decode, RuntimeDebug, Clone, Default, PartialEq, Eq, TypeInfo)]
+pub struct BlockLeaves {
+    /// Positions of the indexed leaves, in the order they were pushed
+    pub leaves: Vec<NodeIndex>,
+    /// Whether the block pushed more than `MaxLeavesPerBlock` leaves, in which case `leaves` only
+    /// holds the first ones
+    pub truncated: bool,
+}
+
+/// Summary of the outgoing requests awaiting relay, published to the offchain storage every block
+/// so relayers can read it in a single query
+#[derive(Encode, Decode, RuntimeDebug, Clone, PartialEq, Eq, TypeInfo)]
+pub struct RelayerHints<BlockNumber> {
+    /// Block the hints were computed at
+    pub block_number: BlockNumber,
+    /// Destinations and commitments of outgoing requests that haven't been timed out
+    pub pending_requests: Vec<(StateMachine, H256)>,
+    /// Commitments of GET requests that have received no response
+    pub pending_get_requests: Vec<H256>,
+}
+
 /// Accumulated Weight consumed by contract callbacks in a transaction
 #[derive(Default, scale_info::TypeInfo, Encode, Decode)]
 pub struct WeightUsed {
@@ -107,3 +384,109 @@ pub struct WeightUsed {
     /// Total weight limit used in executing contract callbacks in a transaction
     pub weight_limit: Weight,
 }
+
+/// Module id that post requests dispatched with delivery options are addressed to, their data is
+/// a [`PostEnvelope`] naming the module they're delivered to
+pub const ENVELOPE_MODULE_ID: PalletId = PalletId(*b"ismp-env");
+
+/// Module id cancellations of outgoing requests are sent from and delivered to, their data is the
+/// encoded post request being cancelled
+pub const CANCELLATION_MODULE_ID: PalletId = PalletId(*b"ismp-cnl");
+
+/// Version of the [`PostEnvelope`] encoding, it's the first byte of the enveloped data
+pub const POST_ENVELOPE_VERSION: u8 = 1;
+
+/// Wraps the data of post requests dispatched with delivery options, the destination unwraps it
+/// before the request is passed to its module
+#[derive(Debug, Clone, Encode, Decode, TypeInfo, PartialEq, Eq)]
+pub struct PostEnvelope {
+    /// Module the request is delivered to
+    pub to: Vec<u8>,
+    /// Whether the destination should respond with an [`AckCode`]
+    pub ack_requested: bool,
+    /// Whether the data was compressed by the sender, it's decompressed before it's passed to
+    /// the destination module
+    pub compressed: bool,
+    /// Data passed to the destination module
+    pub data: Vec<u8>,
+}
+
+impl PostEnvelope {
+    /// Encode the envelope as post request data
+    pub fn to_data(&self) -> Vec<u8> {
+        [&[POST_ENVELOPE_VERSION], self.encode().as_slice()].concat()
+    }
+
+    /// Decode the envelope from post request data
+    pub fn from_data(data: &[u8]) -> Result<Self, ismp_rs::error::Error> {
+        match data.split_first() {
+            Some((&POST_ENVELOPE_VERSION, mut encoded)) => {
+                Self::decode_all(&mut encoded).map_err(|_| {
+                    ismp_rs::error::Error::ImplementationSpecific("Invalid post envelope".into())
+                })
+            }
+            _ => Err(ismp_rs::error::Error::ImplementationSpecific(
+                "Unsupported post envelope version".into(),
+            )),
+        }
+    }
+
+    /// Wrap a post request in an envelope, addressing it to [`ENVELOPE_MODULE_ID`]
+    pub fn seal(mut post: DispatchPost, ack_requested: bool, compressed: bool) -> DispatchPost {
+        let to = core::mem::replace(&mut post.to, ENVELOPE_MODULE_ID.0.to_vec());
+        post.data = Self { to, ack_requested, compressed, data: post.data }.to_data();
+        post
+    }
+
+    /// Unwrap the envelope of a post request addressed to [`ENVELOPE_MODULE_ID`], returning the
+    /// request as it was dispatched by its module, with its data decompressed, and whether the
+    /// sender asked for an acknowledgement. Other requests are returned unchanged.
+    pub fn open(mut post: Post) -> Result<(Post, bool), ismp_rs::error::Error> {
+        if post.to != ENVELOPE_MODULE_ID.0.to_vec() {
+            return Ok((post, false))
+        }
+
+        let envelope = Self::from_data(&post.data)?;
+        if envelope.to == ENVELOPE_MODULE_ID.0.to_vec() {
+            Err(ismp_rs::error::Error::ImplementationSpecific(
+                "Nested post envelopes are not supported".into(),
+            ))?
+        }
+        post.to = envelope.to;
+        post.data = match envelope.compressed {
+            #[cfg(feature = "compression")]
+            true => crate::compression::decompress(&envelope.data)?,
+            #[cfg(not(feature = "compression"))]
+            true => Err(ismp_rs::error::Error::ImplementationSpecific(
+                "Compressed request data is not supported".into(),
+            ))?,
+            false => envelope.data,
+        };
+        Ok((post, envelope.ack_requested))
+    }
+}
+
+/// The outcome of delivering a post request to its destination module, sent back to the source
+/// as the response of requests that asked for it
+#[derive(Debug, Clone, Copy, Encode, Decode, TypeInfo, PartialEq, Eq)]
+pub enum AckCode {
+    /// The destination module accepted the request
+    Success,
+    /// The destination module rejected the request
+    Failure,
+}
+
+/// Derives the key of a storage value in the host's own state, for use in GET requests
+/// dispatched with [`crate::dispatcher::Dispatcher::dispatch_self_get`]
+pub fn storage_value_key(pallet: &str, item: &str) -> Vec<u8> {
+    frame_support::storage::storage_prefix(pallet.as_bytes(), item.as_bytes()).to_vec()
+}
+
+/// Derives the key of an entry in a storage map in the host's own state, for use in GET
+/// requests dispatched with [`crate::dispatcher::Dispatcher::dispatch_self_get`]. `H` must be the
+/// hasher the map was declared with.
+pub fn storage_map_key<H: StorageHasher, K: Encode>(pallet: &str, item: &str, key: &K) -> Vec<u8> {
+    let mut storage_key = storage_value_key(pallet, item);
+    storage_key.extend_from_slice(H::hash(&key.encode()).as_ref());
+    storage_key
+}
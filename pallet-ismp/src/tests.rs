@@ -15,22 +15,28 @@
 
 use crate::{mocks::*, *};
 use std::{
+    collections::BTreeMap,
     ops::Range,
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
-    dispatcher::Dispatcher,
+    dispatcher::{Dispatcher, Receipt},
     mocks::ismp::{setup_mock_client, MOCK_CONSENSUS_STATE_ID},
+    primitives::BlockLeaves,
+    weight_info::WeightProvider,
+};
+use frame_support::{
+    assert_noop, assert_ok,
+    traits::{OffchainWorker, OnFinalize, OnInitialize, OnRuntimeUpgrade},
 };
-use frame_support::traits::OnFinalize;
 use ismp_primitives::mmr::MmrHasher;
 use ismp_rs::{
-    consensus::StateMachineHeight,
+    consensus::{StateCommitment, StateCommitmentHeight, StateMachineHeight},
     host::Ethereum,
-    messaging::{Proof, ResponseMessage, TimeoutMessage},
-    router::{DispatchGet, DispatchRequest, IsmpDispatcher, Post},
-    util::hash_request,
+    messaging::{ConsensusMessage, Proof, RequestMessage, ResponseMessage, TimeoutMessage},
+    router::{DispatchGet, DispatchPost, DispatchRequest, IsmpDispatcher, Post, PostResponse},
+    util::{hash_request, hash_response},
 };
 use ismp_testsuite::{
     check_challenge_period, check_client_expiry, frozen_check, timeout_post_processing_check,
@@ -41,7 +47,11 @@ use sp_core::{
     offchain::{testing::TestOffchainExt, OffchainDbExt, OffchainWorkerExt},
     H256,
 };
-use sp_runtime::BuildStorage;
+use sp_runtime::{
+    traits::ValidateUnsigned,
+    transaction_validity::{InvalidTransaction, TransactionSource},
+    BuildStorage,
+};
 
 pub(crate) fn new_test_ext() -> sp_io::TestExternalities {
     frame_system::GenesisConfig::<Test>::default().build_storage().unwrap().into()
@@ -192,6 +202,103 @@ fn should_generate_and_verify_batch_proof_for_leaves_inserted_across_multiple_bl
     })
 }
 
+#[test]
+fn should_fail_proof_generation_when_offchain_storage_is_behind() {
+    let _ = env_logger::try_init();
+    let mut ext = new_test_ext();
+    let positions = ext.execute_with(|| {
+        let positions = push_leaves(0..3);
+        new_block();
+        positions
+    });
+    ext.persist_offchain_overlay();
+
+    register_offchain_ext(&mut ext);
+    ext.execute_with(move || {
+        assert!(Pallet::<Test>::generate_proof(vec![positions[0]]).is_ok());
+        // these leaves never make it to the offchain db
+        push_leaves(3..5);
+        assert_eq!(
+            Pallet::<Test>::generate_proof(vec![positions[0]]),
+            Err(primitives::Error::InconsistentOffchainStorage)
+        );
+    })
+}
+
+#[test]
+fn should_generate_proofs_against_historical_mmr_roots() {
+    let _ = env_logger::try_init();
+    let mut ext = new_test_ext();
+    let (root, positions) = ext.execute_with(|| {
+        let positions = push_leaves(0..5);
+        new_block();
+        let root = Pallet::<Test>::mmr_root();
+        push_leaves(5..12);
+        new_block();
+        assert_ne!(root, Pallet::<Test>::mmr_root());
+        (root, positions)
+    });
+    ext.persist_offchain_overlay();
+
+    register_offchain_ext(&mut ext);
+    ext.execute_with(move || {
+        let indices = vec![positions[1], positions[4]];
+        let (leaves, proof) = Pallet::<Test>::generate_proof_at_block(indices.clone(), 1).unwrap();
+        assert_eq!(proof.leaf_count, 5);
+
+        let mmr_size = NodesUtils::new(proof.leaf_count).size();
+        let nodes = proof.items.into_iter().map(|h| DataOrHash::Hash(h.into())).collect();
+        let proof = MerkleProof::<DataOrHash, MmrHasher<Host<Test>>>::new(mmr_size, nodes);
+        let calculated_root = proof
+            .calculate_root(
+                indices
+                    .into_iter()
+                    .zip(leaves.into_iter().map(|leaf| DataOrHash::Data(leaf)))
+                    .collect(),
+            )
+            .unwrap();
+
+        assert_eq!(root, calculated_root.hash::<Host<Test>>());
+        // no leaf count was recorded for a block that hasn't been finalized
+        assert!(Pallet::<Test>::generate_proof_at_block(vec![positions[0]], 10).is_err());
+        // a block that didn't modify the mmr shares the root of the block before it
+        new_block();
+        let (_, proof) = Pallet::<Test>::generate_proof_at_block(vec![positions[0]], 3).unwrap();
+        assert_eq!(proof.leaf_count, 12);
+    })
+}
+
+#[test]
+fn should_calculate_peak_positions_correctly() {
+    assert_eq!(NodesUtils::new(0).peak_positions(), Vec::<NodeIndex>::new());
+    assert_eq!(NodesUtils::new(1).peak_positions(), vec![0]);
+    assert_eq!(NodesUtils::new(3).peak_positions(), vec![2, 3]);
+    assert_eq!(NodesUtils::new(7).peak_positions(), vec![6, 9, 10]);
+    assert_eq!(NodesUtils::new(11).peak_positions(), vec![14, 17, 18]);
+}
+
+#[test]
+fn should_compare_leaf_data_with_its_hash() {
+    let post = Post {
+        source: StateMachine::Kusama(2000),
+        dest: StateMachine::Kusama(2001),
+        nonce: 0,
+        from: vec![0u8; 32],
+        to: vec![1u8; 32],
+        timeout_timestamp: 0,
+        data: vec![2u8; 64],
+        gas_limit: 0,
+    };
+    let request = Request::Post(post);
+    let data = DataOrHash::Data(Leaf::Request(request.clone()));
+    let hash = DataOrHash::Hash(hash_request::<Host<Test>>(&request));
+
+    assert_ne!(data, hash);
+    assert!(data.equivalent::<Host<Test>>(&hash));
+    assert!(hash.equivalent::<Host<Test>>(&data));
+    assert!(!data.equivalent::<Host<Test>>(&DataOrHash::Hash(H256::zero())));
+}
+
 fn set_timestamp(now: Option<u64>) {
     Timestamp::set_timestamp(
         now.unwrap_or(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64),
@@ -218,14 +325,109 @@ fn dispatcher_should_write_receipts_for_outgoing_requests_and_responses() {
         };
 
         let request_commitment = hash_request::<Host<Test>>(&Request::Post(post.clone()));
-        RequestCommitments::<Test>::insert(
-            request_commitment,
-            LeafIndexQuery { source_chain: post.source, dest_chain: post.dest, nonce: 0 },
-        );
+        RequestReceipts::<Test>::insert(request_commitment, Receipt::Ok);
         write_outgoing_commitments(&host, &dispatcher).unwrap();
     })
 }
 
+#[test]
+fn dispatcher_should_reject_requests_to_the_host_state_machine() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let dispatcher = Dispatcher::<Test>::default();
+        let post = DispatchPost {
+            dest: host.host_state_machine(),
+            from: vec![0u8; 32],
+            to: vec![0u8; 32],
+            timeout_timestamp: 0,
+            data: vec![0u8; 64],
+            gas_limit: 0,
+        };
+
+        assert!(dispatcher.dispatch_request(DispatchRequest::Post(post)).is_err());
+        assert_eq!(Nonce::<Test>::get(), 0);
+    })
+}
+
+#[test]
+fn host_should_deduplicate_state_commitment_reads() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let commitment = host.state_machine_commitment(height.clone()).unwrap();
+
+        // subsequent reads through the same host are served without touching storage
+        StateCommitments::<Test>::remove(&height);
+        assert_eq!(host.state_machine_commitment(height.clone()).unwrap(), commitment);
+        assert!(Host::<Test>::default().state_machine_commitment(height).is_err());
+    })
+}
+
+#[test]
+fn should_dispatch_requests_from_overridden_host_state_machine() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        frame_system::Pallet::<Test>::set_block_number(1);
+        let host = Host::<Test>::default();
+        assert_eq!(host.host_state_machine(), StateMachine::Kusama(100));
+
+        Ismp::set_host_state_machine(RuntimeOrigin::root(), StateMachine::Kusama(200)).unwrap();
+        assert_eq!(host.host_state_machine(), StateMachine::Kusama(200));
+
+        let request = dispatch_post_with_timeout(&host, 0);
+        assert_eq!(request.source_chain(), StateMachine::Kusama(200));
+        assert!(host.request_commitment(hash_request::<Host<Test>>(&request)).is_ok());
+
+        // the identity can't change while the request is in flight
+        assert_eq!(
+            Ismp::set_host_state_machine(RuntimeOrigin::root(), StateMachine::Kusama(300)),
+            Err(Error::<Test>::OutgoingRequestsInFlight.into())
+        );
+    })
+}
+
+#[test]
+fn should_store_challenge_periods_per_consensus_state() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        set_timestamp(None);
+        let host = Host::<Test>::default();
+        let create = |consensus_state_id, challenge_period| CreateConsensusState {
+            consensus_state: vec![],
+            consensus_client_id: MOCK_CONSENSUS_STATE_ID,
+            consensus_state_id,
+            unbonding_period: 1_000_000,
+            challenge_period,
+            state_machine_commitments: vec![],
+        };
+
+        // Two consensus states backed by the same consensus client
+        Ismp::create_consensus_client(RuntimeOrigin::root(), create(*b"mck1", 60)).unwrap();
+        Ismp::create_consensus_client(RuntimeOrigin::root(), create(*b"mck2", 60 * 60)).unwrap();
+
+        assert_eq!(host.challenge_period(*b"mck1"), Some(Duration::from_secs(60)));
+        assert_eq!(host.challenge_period(*b"mck2"), Some(Duration::from_secs(60 * 60)));
+        assert_eq!(host.consensus_client_id(*b"mck1"), Some(MOCK_CONSENSUS_STATE_ID));
+        assert_eq!(host.consensus_client_id(*b"mck2"), Some(MOCK_CONSENSUS_STATE_ID));
+    })
+}
+
+#[test]
+fn should_reject_empty_message_batches() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let origin = RuntimeOrigin::signed(sp_core::sr25519::Public::from_raw([0u8; 32]));
+        assert_noop!(Ismp::handle(origin, vec![]), Error::<Test>::InvalidMessage);
+    })
+}
+
 #[test]
 fn should_reject_updates_within_challenge_period() {
     let mut ext = new_test_ext();
@@ -276,6 +478,72 @@ fn should_handle_post_request_timeouts_correctly() {
     })
 }
 
+fn dispatch_post_with_timeout(host: &Host<Test>, timeout_timestamp: u64) -> Request {
+    let dispatcher = Dispatcher::<Test>::default();
+    let post = DispatchPost {
+        dest: StateMachine::Ethereum(Ethereum::ExecutionLayer),
+        from: vec![0u8; 32],
+        to: vec![0u8; 32],
+        timeout_timestamp,
+        data: vec![0u8; 64],
+        gas_limit: 0,
+    };
+    let nonce = Nonce::<Test>::get();
+    dispatcher.dispatch_request(DispatchRequest::Post(post)).unwrap();
+
+    Request::Post(Post {
+        source: host.host_state_machine(),
+        dest: StateMachine::Ethereum(Ethereum::ExecutionLayer),
+        nonce,
+        from: vec![0u8; 32],
+        to: vec![0u8; 32],
+        timeout_timestamp,
+        data: vec![0u8; 64],
+        gas_limit: 0,
+    })
+}
+
+#[test]
+fn should_accept_post_request_timeouts_with_non_membership_proof() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        host.store_challenge_period(MOCK_CONSENSUS_STATE_ID, 60 * 60).unwrap();
+        let request = dispatch_post_with_timeout(&host, 500);
+        let commitment = hash_request::<Host<Test>>(&request);
+
+        let timeout_msg = TimeoutMessage::Post {
+            requests: vec![request],
+            timeout_proof: Proof { height, proof: vec![] },
+        };
+
+        Pallet::<Test>::handle_messages(vec![Message::Timeout(timeout_msg)]).unwrap();
+        assert!(host.request_commitment(commitment).is_err());
+    })
+}
+
+#[test]
+fn should_reject_post_request_timeouts_with_membership_proof() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        host.store_challenge_period(MOCK_CONSENSUS_STATE_ID, 60 * 60).unwrap();
+        let request = dispatch_post_with_timeout(&host, 500);
+        let commitment = hash_request::<Host<Test>>(&request);
+
+        // The proof shows the request was received on the destination
+        let timeout_msg = TimeoutMessage::Post {
+            requests: vec![request],
+            timeout_proof: Proof { height, proof: vec![1u8; 32] },
+        };
+
+        Pallet::<Test>::handle_messages(vec![Message::Timeout(timeout_msg)]).unwrap();
+        assert!(host.request_commitment(commitment).is_ok());
+    })
+}
+
 #[test]
 fn should_handle_get_request_timeouts_correctly() {
     let mut ext = new_test_ext();
@@ -382,3 +650,2844 @@ fn should_handle_get_request_responses_correctly() {
         }
     })
 }
+
+#[test]
+fn should_bound_and_finalize_pending_consensus_updates() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        frame_system::Pallet::<Test>::set_block_number(1);
+        set_timestamp(Some(1_000_000));
+        let host = Host::<Test>::default();
+        host.store_challenge_period(MOCK_CONSENSUS_STATE_ID, 60).unwrap();
+        host.store_consensus_update_time(MOCK_CONSENSUS_STATE_ID, host.timestamp()).unwrap();
+        let id = StateMachineId {
+            state_id: StateMachine::Kusama(2000),
+            consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+        };
+        let updates = (0..10)
+            .map(|height| {
+                (StateMachineHeight { id, height }, StateMachineHeight { id, height: height + 1 })
+            })
+            .collect::<BTreeSet<_>>();
+
+        Pallet::<Test>::store_pending_consensus_updates(MOCK_CONSENSUS_STATE_ID, updates);
+        let pending = ConsensusUpdateResults::<Test>::get(MOCK_CONSENSUS_STATE_ID).unwrap();
+        // only the most recent updates are retained
+        assert_eq!(pending.len(), 5);
+        assert!(pending.iter().all(|(_, _, latest)| latest.height > 5));
+        // and the evicted ones are reported
+        frame_system::Pallet::<Test>::assert_last_event(RuntimeEvent::Ismp(
+            Event::PendingConsensusUpdatesEvicted {
+                consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+                evicted: (0..5)
+                    .map(|height| {
+                        (
+                            StateMachineHeight { id, height },
+                            StateMachineHeight { id, height: height + 1 },
+                        )
+                    })
+                    .collect(),
+            },
+        ));
+
+        // challenge period has not elapsed
+        Ismp::on_initialize(1);
+        assert!(ConsensusUpdateResults::<Test>::get(MOCK_CONSENSUS_STATE_ID).is_some());
+
+        set_timestamp(Some(1_000_000 + 60_000));
+        Ismp::on_initialize(2);
+        assert!(ConsensusUpdateResults::<Test>::get(MOCK_CONSENSUS_STATE_ID).is_none());
+    })
+}
+
+#[test]
+fn should_not_store_empty_pending_consensus_updates() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        setup_mock_client::<_, Test>(&host);
+        host.store_challenge_period(MOCK_CONSENSUS_STATE_ID, 60).unwrap();
+
+        // the mock consensus client doesn't return any state machine updates
+        Pallet::<Test>::handle_messages(vec![Message::Consensus(ConsensusMessage {
+            consensus_proof: vec![],
+            consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+        })])
+        .unwrap();
+        assert_eq!(ConsensusUpdateResults::<Test>::iter().count(), 0);
+    })
+}
+
+#[test]
+fn should_reject_oversized_get_responses() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let dispatcher = Dispatcher::<Test>::default();
+        let get = |nonce, keys: usize| {
+            let keys = (0..keys as u8).map(|i| vec![i]).collect::<Vec<_>>();
+            dispatcher
+                .dispatch_request(DispatchRequest::Get(DispatchGet {
+                    dest: height.id.state_id,
+                    from: vec![0u8; 32],
+                    keys: keys.clone(),
+                    height: height.height,
+                    timeout_timestamp: 0,
+                    gas_limit: 0,
+                }))
+                .unwrap();
+            Request::Get(ismp_rs::router::Get {
+                source: host.host_state_machine(),
+                dest: height.id.state_id,
+                nonce,
+                from: vec![0u8; 32],
+                keys,
+                height: height.height,
+                timeout_timestamp: 0,
+                gas_limit: 0,
+            })
+        };
+        // the mock proves every key with the proof bytes as its value
+        let response = |request: Request, value_size| {
+            Message::Response(ResponseMessage::Get {
+                requests: vec![request],
+                proof: Proof { height: height.clone(), proof: vec![0u8; value_size] },
+            })
+        };
+
+        // within bounds
+        assert_eq!(Pallet::<Test>::execute_messages(vec![response(get(0, 32), 64)]), vec![]);
+
+        // too many values
+        let request = get(1, 33);
+        let errors = Pallet::<Test>::execute_messages(vec![response(request.clone(), 1)]);
+        assert!(matches!(&errors[..], [HandlingError::ModuleDispatchError { nonce: 1, .. }]));
+        assert!(frame_support::storage::unhashed::get_raw(&mocks::ismp::get_response_key(
+            &request
+        ))
+        .is_none());
+
+        // value too large
+        let request = get(2, 1);
+        let errors = Pallet::<Test>::execute_messages(vec![response(request.clone(), 65)]);
+        assert!(matches!(&errors[..], [HandlingError::ModuleDispatchError { nonce: 2, .. }]));
+        assert!(frame_support::storage::unhashed::get_raw(&mocks::ismp::get_response_key(
+            &request
+        ))
+        .is_none());
+    })
+}
+
+#[test]
+fn should_merge_consecutive_consensus_updates_within_challenge_period() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        let update = |state_id, height| {
+            let id = StateMachineId { state_id, consensus_state_id: MOCK_CONSENSUS_STATE_ID };
+            (StateMachineHeight { id, height }, StateMachineHeight { id, height: height + 1 })
+        };
+        let first = BTreeSet::from([update(StateMachine::Kusama(2000), 1)]);
+        let second = BTreeSet::from([update(StateMachine::Kusama(2001), 1)]);
+
+        Pallet::<Test>::store_pending_consensus_updates(MOCK_CONSENSUS_STATE_ID, first.clone());
+        Pallet::<Test>::store_pending_consensus_updates(MOCK_CONSENSUS_STATE_ID, second.clone());
+
+        let pending = ConsensusUpdateResults::<Test>::get(MOCK_CONSENSUS_STATE_ID)
+            .unwrap()
+            .into_iter()
+            .map(|(_, previous, latest)| (previous, latest))
+            .collect::<BTreeSet<_>>();
+        assert_eq!(pending, first.union(&second).cloned().collect());
+    })
+}
+
+#[test]
+fn should_finalize_pending_consensus_updates_by_their_own_update_time() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        host.store_challenge_period(MOCK_CONSENSUS_STATE_ID, 60).unwrap();
+        let update = |state_id, height| {
+            let id = StateMachineId { state_id, consensus_state_id: MOCK_CONSENSUS_STATE_ID };
+            (StateMachineHeight { id, height }, StateMachineHeight { id, height: height + 1 })
+        };
+
+        set_timestamp(Some(1_000_000));
+        host.store_consensus_update_time(MOCK_CONSENSUS_STATE_ID, host.timestamp()).unwrap();
+        Pallet::<Test>::store_pending_consensus_updates(
+            MOCK_CONSENSUS_STATE_ID,
+            BTreeSet::from([update(StateMachine::Kusama(2000), 1)]),
+        );
+
+        // a later update within the challenge period doesn't delay the first one
+        set_timestamp(Some(1_000_000 + 30_000));
+        host.store_consensus_update_time(MOCK_CONSENSUS_STATE_ID, host.timestamp()).unwrap();
+        Pallet::<Test>::store_pending_consensus_updates(
+            MOCK_CONSENSUS_STATE_ID,
+            BTreeSet::from([update(StateMachine::Kusama(2001), 1)]),
+        );
+
+        frame_system::Pallet::<Test>::set_block_number(1);
+        set_timestamp(Some(1_000_000 + 60_000));
+        Ismp::on_initialize(1);
+        let pending = ConsensusUpdateResults::<Test>::get(MOCK_CONSENSUS_STATE_ID).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert!(pending
+            .iter()
+            .all(|(_, _, latest)| latest.id.state_id == StateMachine::Kusama(2001)));
+        assert!(frame_system::Pallet::<Test>::events().iter().any(|record| matches!(
+            &record.event,
+            RuntimeEvent::Ismp(Event::StateMachineUpdated { state_machine_id, latest_height: 2 })
+                if state_machine_id.state_id == StateMachine::Kusama(2000)
+        )));
+
+        set_timestamp(Some(1_000_000 + 90_000));
+        Ismp::on_initialize(2);
+        assert!(ConsensusUpdateResults::<Test>::get(MOCK_CONSENSUS_STATE_ID).is_none());
+    })
+}
+
+#[test]
+fn should_bound_consensus_states_finalized_per_block() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let limit = <Test as Config>::MaxConsensusStatesFinalizedPerBlock::get() as usize;
+        let ids = (0..limit as u32 + 1).map(|i| i.to_be_bytes()).collect::<Vec<_>>();
+        for id in &ids {
+            host.store_challenge_period(*id, 0).unwrap();
+            let state_machine =
+                StateMachineId { state_id: StateMachine::Kusama(2000), consensus_state_id: *id };
+            Pallet::<Test>::store_pending_consensus_updates(
+                *id,
+                BTreeSet::from([(
+                    StateMachineHeight { id: state_machine, height: 1 },
+                    StateMachineHeight { id: state_machine, height: 2 },
+                )]),
+            );
+        }
+
+        Ismp::on_initialize(1);
+        assert_eq!(ConsensusUpdateResults::<Test>::iter().count(), 1);
+        assert!(ConsensusUpdateResultsCursor::<Test>::get().is_some());
+
+        Ismp::on_initialize(2);
+        assert_eq!(ConsensusUpdateResults::<Test>::iter().count(), 0);
+        assert!(ConsensusUpdateResultsCursor::<Test>::get().is_none());
+    })
+}
+
+#[test]
+fn should_report_module_dispatch_errors() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        frame_system::Pallet::<Test>::set_block_number(1);
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let post = Post {
+            source: height.id.state_id,
+            dest: host.host_state_machine(),
+            nonce: 0,
+            from: vec![0u8; 32],
+            to: mocks::ismp::MODULE_ID.to_bytes(),
+            timeout_timestamp: 0,
+            data: mocks::ismp::REJECTED_REQUEST_DATA.to_vec(),
+            gas_limit: 0,
+        };
+
+        // the module rejects the request
+        let request =
+            RequestMessage { requests: vec![post], proof: Proof { height, proof: vec![] } };
+        Pallet::<Test>::handle_messages(vec![Message::Request(request)]).unwrap();
+
+        let reported = frame_system::Pallet::<Test>::events().into_iter().any(|record| {
+            matches!(
+                record.event,
+                RuntimeEvent::Ismp(Event::HandlingErrors { ref errors })
+                    if errors.iter().any(|error| matches!(
+                        error,
+                        HandlingError::ModuleDispatchError { nonce: 0, .. }
+                    ))
+            )
+        });
+        assert!(reported);
+    })
+}
+
+#[test]
+fn should_version_stored_consensus_states() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        host.store_consensus_state(MOCK_CONSENSUS_STATE_ID, vec![7u8, 8, 9]).unwrap();
+        assert_eq!(
+            ConsensusStates::<Test>::get(MOCK_CONSENSUS_STATE_ID),
+            Some(vec![primitives::CONSENSUS_STATE_VERSION, 7, 8, 9])
+        );
+        assert_eq!(host.consensus_state(MOCK_CONSENSUS_STATE_ID).unwrap(), vec![7u8, 8, 9]);
+
+        // states without the current version are rejected
+        ConsensusStates::<Test>::insert(*b"lgcy", vec![7u8, 8, 9]);
+        assert!(host.consensus_state(*b"lgcy").is_err());
+        assert_eq!(Pallet::<Test>::get_consensus_state(*b"lgcy"), None);
+    })
+}
+
+#[test]
+fn should_warn_about_host_timestamp_drift() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        frame_system::Pallet::<Test>::set_block_number(1);
+        let host = Host::<Test>::default();
+        // both the host and the commitment are at 1000 seconds
+        let height = setup_mock_client::<_, Test>(&host);
+        let drift_detected = || {
+            frame_system::Pallet::<Test>::events().into_iter().any(|record| {
+                matches!(record.event, RuntimeEvent::Ismp(Event::TimestampDriftDetected { .. }))
+            })
+        };
+
+        Pallet::<Test>::check_timestamp_drift(&host, &height);
+        assert!(!drift_detected());
+
+        // move the host two hours ahead of the commitment
+        set_timestamp(Some((1000 + 2 * 60 * 60) * 1000));
+        Pallet::<Test>::check_timestamp_drift(&host, &height);
+        assert!(drift_detected());
+    })
+}
+
+#[test]
+fn should_query_frozen_clients_and_state_machines() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        assert!(Pallet::<Test>::get_frozen_consensus_clients().is_empty());
+        assert!(Pallet::<Test>::get_frozen_state_machines().is_empty());
+
+        host.freeze_consensus_client(MOCK_CONSENSUS_STATE_ID).unwrap();
+        host.freeze_state_machine(height.clone()).unwrap();
+
+        assert_eq!(Pallet::<Test>::get_frozen_consensus_clients(), vec![MOCK_CONSENSUS_STATE_ID]);
+        assert_eq!(Pallet::<Test>::get_frozen_state_machines(), vec![height.id]);
+    })
+}
+
+#[test]
+fn should_fetch_request_by_commitment() {
+    let mut ext = new_test_ext();
+    let request = ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        dispatch_post_with_timeout(&host, 0)
+    });
+    ext.persist_offchain_overlay();
+
+    register_offchain_ext(&mut ext);
+    ext.execute_with(|| {
+        let commitment = hash_request::<Host<Test>>(&request);
+        assert_eq!(Pallet::<Test>::request_by_commitment(commitment), Some(request));
+        assert_eq!(Pallet::<Test>::request_by_commitment(H256::zero()), None);
+        assert_eq!(Pallet::<Test>::response_by_commitment(commitment), None);
+    })
+}
+
+#[test]
+fn should_reject_initial_commitments_with_invalid_timestamps() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let create = |timestamp| CreateConsensusState {
+            consensus_state: vec![],
+            consensus_client_id: MOCK_CONSENSUS_STATE_ID,
+            consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+            unbonding_period: 1_000_000,
+            challenge_period: 0,
+            state_machine_commitments: vec![(
+                StateMachineId {
+                    state_id: StateMachine::Ethereum(Ethereum::ExecutionLayer),
+                    consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+                },
+                StateCommitmentHeight {
+                    commitment: StateCommitment {
+                        timestamp,
+                        overlay_root: None,
+                        state_root: Default::default(),
+                    },
+                    height: 3,
+                },
+            )],
+        };
+
+        // timestamp in milliseconds
+        assert_noop!(
+            Ismp::create_consensus_client(RuntimeOrigin::root(), create(1_651_280_681_000)),
+            Error::<Test>::InvalidCommitmentTimestamp
+        );
+        assert_noop!(
+            Ismp::create_consensus_client(RuntimeOrigin::root(), create(1000)),
+            Error::<Test>::InvalidCommitmentTimestamp
+        );
+        assert_ok!(Ismp::create_consensus_client(RuntimeOrigin::root(), create(1_651_280_681)));
+    })
+}
+
+#[test]
+fn should_reject_consensus_updates_within_the_min_update_interval() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        frame_system::Pallet::<Test>::set_block_number(1);
+        let host = Host::<Test>::default();
+        setup_mock_client::<_, Test>(&host);
+        Ismp::update_consensus_state(
+            RuntimeOrigin::root(),
+            UpdateConsensusState {
+                consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+                unbonding_period: None,
+                challenge_period: None,
+                min_update_interval: Some(60),
+            },
+        )
+        .unwrap();
+
+        let update = || {
+            frame_system::Pallet::<Test>::reset_events();
+            Pallet::<Test>::handle_messages(vec![Message::Consensus(ConsensusMessage {
+                consensus_proof: vec![],
+                consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+            })])
+            .unwrap();
+            frame_system::Pallet::<Test>::events().into_iter().any(|record| {
+                matches!(
+                    record.event,
+                    RuntimeEvent::Ismp(Event::HandlingErrors { ref errors })
+                        if matches!(errors[..], [HandlingError::UpdateIntervalNotElapsed { .. }])
+                )
+            })
+        };
+
+        // the client was created at 1000 seconds
+        set_timestamp(Some(1100 * 1000));
+        assert!(!update());
+        assert_eq!(host.consensus_update_time(MOCK_CONSENSUS_STATE_ID).unwrap().as_secs(), 1100);
+
+        set_timestamp(Some(1130 * 1000));
+        assert!(update());
+        assert_eq!(host.consensus_update_time(MOCK_CONSENSUS_STATE_ID).unwrap().as_secs(), 1100);
+    })
+}
+
+#[test]
+fn should_cache_verified_state_proof_values_in_the_host() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let state_machine = host
+            .consensus_client(MOCK_CONSENSUS_STATE_ID)
+            .unwrap()
+            .state_machine(height.id.state_id)
+            .unwrap();
+        let root = host.state_machine_commitment(height.clone()).unwrap();
+        let proof = |proof: Vec<u8>| Proof { height: height.clone(), proof };
+
+        // the mock proves every key with the proof bytes as its value
+        let values = state_machine
+            .verify_state_proof(&host, vec![vec![1], vec![2]], root.clone(), &proof(vec![7]))
+            .unwrap();
+        assert_eq!(values.get(&vec![1]), Some(&Some(vec![7])));
+
+        // a second read of the same keys is served from the cache without verifying the proof,
+        // only the new key is verified
+        let values = state_machine
+            .verify_state_proof(&host, vec![vec![2], vec![3]], root.clone(), &proof(vec![9]))
+            .unwrap();
+        assert_eq!(values.get(&vec![2]), Some(&Some(vec![7])));
+        assert_eq!(values.get(&vec![3]), Some(&Some(vec![9])));
+
+        // the cache is held by the host, a new host verifies the proof again
+        let host = Host::<Test>::default();
+        let state_machine = host
+            .consensus_client(MOCK_CONSENSUS_STATE_ID)
+            .unwrap()
+            .state_machine(height.id.state_id)
+            .unwrap();
+        let values =
+            state_machine.verify_state_proof(&host, vec![vec![2]], root, &proof(vec![9])).unwrap();
+        assert_eq!(values.get(&vec![2]), Some(&Some(vec![9])));
+    })
+}
+
+#[test]
+fn should_restrict_consensus_messages_to_allowed_submitters() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let allowed = sp_core::sr25519::Public::from_raw([1u8; 32]);
+        let disallowed = sp_core::sr25519::Public::from_raw([2u8; 32]);
+        let consensus_message = || {
+            vec![Message::Consensus(ConsensusMessage {
+                consensus_proof: vec![],
+                consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+            })]
+        };
+
+        // anyone can submit consensus messages while the allowlist is empty
+        assert_ok!(Ismp::handle(RuntimeOrigin::signed(disallowed), consensus_message()));
+
+        assert_ok!(Ismp::set_consensus_submitters(
+            RuntimeOrigin::root(),
+            BTreeSet::from([allowed])
+        ));
+        assert_ok!(Ismp::handle(RuntimeOrigin::signed(allowed), consensus_message()));
+        assert_noop!(
+            Ismp::handle(RuntimeOrigin::signed(disallowed), consensus_message()),
+            Error::<Test>::UnauthorizedConsensusSubmitter
+        );
+
+        // other messages can still be submitted by anyone
+        let request_message = Message::Request(RequestMessage {
+            requests: vec![],
+            proof: Proof { height, proof: vec![] },
+        });
+        assert_ok!(Ismp::handle(RuntimeOrigin::signed(disallowed), vec![request_message]));
+    })
+}
+
+#[test]
+fn dispatcher_should_only_respond_to_received_requests() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let dispatcher = Dispatcher::<Test>::default();
+        let post = Post {
+            source: StateMachine::Kusama(2000),
+            dest: host.host_state_machine(),
+            nonce: 0,
+            from: vec![0u8; 32],
+            to: vec![0u8; 32],
+            timeout_timestamp: 0,
+            data: vec![0u8; 64],
+            gas_limit: 0,
+        };
+        let response = PostResponse { post: post.clone(), response: vec![1u8; 64] };
+        let response_commitment = hash_response::<Host<Test>>(&Response::Post(response.clone()));
+
+        assert!(dispatcher.dispatch_response(response.clone()).is_err());
+
+        let request_commitment = hash_request::<Host<Test>>(&Request::Post(post));
+        RequestReceipts::<Test>::insert(request_commitment, Receipt::Ok);
+        dispatcher.dispatch_response(response).unwrap();
+        assert!(ResponseCommitments::<Test>::contains_key(response_commitment));
+    })
+}
+
+#[test]
+fn should_report_the_kind_of_dispatch_failures() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let request = Request::Post(Post {
+            source: host.host_state_machine(),
+            dest: StateMachine::Kusama(2000),
+            nonce: 0,
+            from: vec![0u8; 32],
+            to: vec![0u8; 32],
+            timeout_timestamp: 0,
+            data: vec![0u8; 64],
+            gas_limit: 0,
+        });
+
+        Pallet::<Test>::dispatch_request(request.clone()).unwrap();
+        let err = Pallet::<Test>::dispatch_request(request).unwrap_err();
+        assert_eq!(err.kind, primitives::DispatchErrorKind::Duplicate);
+        assert_eq!(err.to_string(), "Duplicate request");
+
+        let err = Dispatcher::<Test>::default()
+            .try_dispatch_request(DispatchRequest::Post(DispatchPost {
+                dest: host.host_state_machine(),
+                from: vec![0u8; 32],
+                to: vec![0u8; 32],
+                timeout_timestamp: 0,
+                data: vec![0u8; 64],
+                gas_limit: 0,
+            }))
+            .unwrap_err();
+        assert_eq!(err.kind, primitives::DispatchErrorKind::DestinationIsHost);
+
+        // the message is kept when converted into an ismp error
+        let err = Dispatcher::<Test>::default()
+            .dispatch_request(DispatchRequest::Post(DispatchPost {
+                dest: host.host_state_machine(),
+                from: vec![0u8; 32],
+                to: vec![0u8; 32],
+                timeout_timestamp: 0,
+                data: vec![0u8; 64],
+                gas_limit: 0,
+            }))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ismp_rs::error::Error::ImplementationSpecific(msg)
+                if msg.starts_with("Cannot dispatch request to the host state machine")
+        ));
+    })
+}
+
+#[test]
+fn should_read_and_delete_response_commitments() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let post = Post {
+            source: StateMachine::Kusama(2000),
+            dest: host.host_state_machine(),
+            nonce: 0,
+            from: vec![0u8; 32],
+            to: vec![0u8; 32],
+            timeout_timestamp: 0,
+            data: vec![0u8; 64],
+            gas_limit: 0,
+        };
+        let response = Response::Post(PostResponse { post, response: vec![1u8; 64] });
+        let commitment = hash_response::<Host<Test>>(&response);
+
+        assert!(host.response_commitment(&response).is_err());
+
+        ResponseCommitments::<Test>::insert(commitment, Receipt::Ok);
+        assert_eq!(host.response_commitment(&response).unwrap(), commitment);
+
+        host.delete_response_commitment(&response).unwrap();
+        assert!(!ResponseCommitments::<Test>::contains_key(commitment));
+        assert!(host.response_commitment(&response).is_err());
+    })
+}
+
+#[test]
+fn should_apply_consensus_batches_atomically() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let origin = RuntimeOrigin::signed(sp_core::sr25519::Public::from_raw([0u8; 32]));
+        let height = setup_mock_client::<_, Test>(&host);
+        let mut create = CreateConsensusState {
+            consensus_state: vec![],
+            consensus_client_id: MOCK_CONSENSUS_STATE_ID,
+            consensus_state_id: *b"mck2",
+            unbonding_period: 1_000_000,
+            challenge_period: 0,
+            state_machine_commitments: vec![],
+        };
+        ismp_rs::handlers::create_client(&host, create.clone()).unwrap();
+        let update = |consensus_state_id| {
+            Message::Consensus(ConsensusMessage { consensus_proof: vec![], consensus_state_id })
+        };
+
+        set_timestamp(Some(1100 * 1000));
+        assert_ok!(Ismp::handle_consensus_batch(
+            origin.clone(),
+            vec![update(MOCK_CONSENSUS_STATE_ID), update(*b"mck2")]
+        ));
+        assert_eq!(host.consensus_update_time(*b"mck2").unwrap().as_secs(), 1100);
+
+        // the second update fails since its consensus client is frozen
+        create.consensus_state_id = *b"mck3";
+        ismp_rs::handlers::create_client(&host, create).unwrap();
+        host.freeze_consensus_client(*b"mck3").unwrap();
+        set_timestamp(Some(1200 * 1000));
+        assert_noop!(
+            Ismp::handle_consensus_batch(
+                origin.clone(),
+                vec![update(MOCK_CONSENSUS_STATE_ID), update(*b"mck3")]
+            ),
+            Error::<Test>::ConsensusBatchFailed
+        );
+        assert_eq!(host.consensus_update_time(MOCK_CONSENSUS_STATE_ID).unwrap().as_secs(), 1100);
+
+        // only consensus messages can be batched
+        let request_message = Message::Request(RequestMessage {
+            requests: vec![],
+            proof: Proof { height, proof: vec![] },
+        });
+        assert_noop!(
+            Ismp::handle_consensus_batch(origin, vec![request_message]),
+            Error::<Test>::InvalidMessage
+        );
+    })
+}
+
+#[test]
+fn should_handle_unsigned_messages() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let messages = vec![Message::Request(RequestMessage {
+            requests: vec![],
+            proof: Proof { height, proof: vec![] },
+        })];
+        let call = Call::<Test>::handle_unsigned { messages: messages.clone() };
+
+        assert!(Ismp::validate_unsigned(TransactionSource::External, &call).is_ok());
+        assert!(Ismp::validate_unsigned(
+            TransactionSource::External,
+            &Call::<Test>::handle_unsigned { messages: vec![] }
+        )
+        .is_err());
+
+        let origin = RuntimeOrigin::signed(sp_core::sr25519::Public::from_raw([0u8; 32]));
+        assert!(Ismp::handle_unsigned(origin, messages.clone()).is_err());
+        let post_info = Ismp::handle_unsigned(RuntimeOrigin::none(), messages).unwrap();
+        assert_eq!(post_info.pays_fee, Pays::No);
+    })
+}
+
+#[test]
+fn should_reject_unsigned_messages_that_were_already_handled() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let post = |nonce| Post {
+            source: height.id.state_id,
+            dest: host.host_state_machine(),
+            nonce,
+            from: vec![0u8; 32],
+            to: mocks::ismp::MODULE_ID.to_bytes(),
+            timeout_timestamp: 0,
+            data: b"hello".to_vec(),
+            gas_limit: 0,
+        };
+        let call = |requests| Call::<Test>::handle_unsigned {
+            messages: vec![Message::Request(RequestMessage {
+                requests,
+                proof: Proof { height: height.clone(), proof: vec![] },
+            })],
+        };
+
+        let first = call(vec![post(0)]);
+        // an overlapping batch is a different transaction in the pool
+        let overlapping = call(vec![post(0), post(1)]);
+        assert!(Ismp::validate_unsigned(TransactionSource::External, &first).is_ok());
+        assert!(Ismp::validate_unsigned(TransactionSource::External, &overlapping).is_ok());
+
+        assert_ok!(Ismp::pre_dispatch(&first));
+        let Call::handle_unsigned { messages } = first.clone() else { unreachable!() };
+        assert_ok!(Ismp::handle_unsigned(RuntimeOrigin::none(), messages));
+
+        // once the first batch is handled, the others are stale
+        for call in [first, overlapping] {
+            assert_eq!(Ismp::pre_dispatch(&call), Err(InvalidTransaction::Stale.into()));
+            assert_eq!(
+                Ismp::validate_unsigned(TransactionSource::External, &call),
+                InvalidTransaction::Stale.into()
+            );
+        }
+        assert_ok!(Ismp::pre_dispatch(&call(vec![post(1)])));
+    })
+}
+
+#[test]
+fn should_reject_unverified_unsigned_messages() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let unknown_height = StateMachineHeight {
+            id: StateMachineId {
+                state_id: StateMachine::Kusama(2000),
+                consensus_state_id: *b"unkn",
+            },
+            height: 1,
+        };
+        let invalid = Call::<Test>::handle_unsigned {
+            messages: vec![Message::Request(RequestMessage {
+                requests: vec![],
+                proof: Proof { height: unknown_height, proof: vec![] },
+            })],
+        };
+        assert_eq!(
+            Ismp::validate_unsigned(TransactionSource::External, &invalid),
+            InvalidTransaction::Call.into()
+        );
+        // the messages aren't handled again when the transaction is included
+        assert_ok!(Ismp::pre_dispatch(&invalid));
+
+        // unsigned messages are rejected once relayers are restricted
+        let messages = vec![Message::Request(RequestMessage {
+            requests: vec![],
+            proof: Proof { height, proof: vec![] },
+        })];
+        let call = Call::<Test>::handle_unsigned { messages: messages.clone() };
+        assert!(Ismp::validate_unsigned(TransactionSource::External, &call).is_ok());
+        assert_ok!(Ismp::add_relayer(
+            RuntimeOrigin::root(),
+            sp_core::sr25519::Public::from_raw([1u8; 32])
+        ));
+        assert_eq!(
+            Ismp::validate_unsigned(TransactionSource::External, &call),
+            InvalidTransaction::BadSigner.into()
+        );
+        assert_eq!(Ismp::pre_dispatch(&call), Err(InvalidTransaction::BadSigner.into()));
+        assert_noop!(
+            Ismp::handle_unsigned(RuntimeOrigin::none(), messages),
+            Error::<Test>::UnauthorizedRelayer
+        );
+    })
+}
+
+#[test]
+fn should_reject_messages_while_paused() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        frame_system::Pallet::<Test>::set_block_number(1);
+        let host = Host::<Test>::default();
+        setup_mock_client::<_, Test>(&host);
+        let origin = RuntimeOrigin::signed(sp_core::sr25519::Public::from_raw([0u8; 32]));
+        let messages = || {
+            vec![Message::Consensus(ConsensusMessage {
+                consensus_proof: vec![],
+                consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+            })]
+        };
+
+        assert_ok!(Ismp::set_paused(RuntimeOrigin::root(), true));
+        frame_system::Pallet::<Test>::assert_last_event(RuntimeEvent::Ismp(Event::Paused));
+        assert_noop!(Ismp::handle(origin.clone(), messages()), Error::<Test>::Paused);
+        assert_noop!(
+            Ismp::handle_unsigned(RuntimeOrigin::none(), messages()),
+            Error::<Test>::Paused
+        );
+        assert_noop!(
+            Ismp::create_consensus_client(
+                RuntimeOrigin::root(),
+                CreateConsensusState {
+                    consensus_state: vec![],
+                    consensus_client_id: MOCK_CONSENSUS_STATE_ID,
+                    consensus_state_id: *b"mck2",
+                    unbonding_period: 1_000_000,
+                    challenge_period: 0,
+                    state_machine_commitments: vec![],
+                }
+            ),
+            Error::<Test>::Paused
+        );
+
+        assert_ok!(Ismp::set_paused(RuntimeOrigin::root(), false));
+        frame_system::Pallet::<Test>::assert_last_event(RuntimeEvent::Ismp(Event::Unpaused));
+        assert_ok!(Ismp::handle(origin, messages()));
+    })
+}
+
+#[test]
+fn should_index_request_nonces_by_block() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        set_timestamp(None);
+        frame_system::Pallet::<Test>::set_block_number(5);
+        let host = Host::<Test>::default();
+        let request = dispatch_post_with_timeout(&host, 0);
+        assert_eq!(Pallet::<Test>::get_request_block(request.nonce()), Some(5));
+
+        host.delete_request_commitment(&request).unwrap();
+        assert_eq!(Pallet::<Test>::get_request_block(request.nonce()), None);
+    })
+}
+
+#[test]
+fn should_only_reject_messages_for_paused_state_machines() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        frame_system::Pallet::<Test>::set_block_number(1);
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let mut other_height = height.clone();
+        other_height.id.state_id = StateMachine::Polkadot(2000);
+        let request_message = |height: StateMachineHeight| {
+            Message::Request(RequestMessage {
+                requests: vec![],
+                proof: Proof { height, proof: vec![] },
+            })
+        };
+        let paused_errors = || {
+            frame_system::Pallet::<Test>::events()
+                .into_iter()
+                .filter_map(|record| match record.event {
+                    RuntimeEvent::Ismp(Event::HandlingErrors { errors }) => Some(errors),
+                    _ => None,
+                })
+                .flatten()
+                .filter(|error| {
+                    matches!(
+                        error,
+                        HandlingError::PausedStateMachine { .. } |
+                            HandlingError::PausedConsensusClient { .. }
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_ok!(Ismp::pause_state_machine(RuntimeOrigin::root(), height.id, true));
+        Pallet::<Test>::handle_messages(vec![
+            request_message(height.clone()),
+            request_message(other_height.clone()),
+        ])
+        .unwrap();
+        assert_eq!(paused_errors(), vec![HandlingError::PausedStateMachine { id: height.id }]);
+
+        frame_system::Pallet::<Test>::reset_events();
+        assert_ok!(Ismp::pause_state_machine(RuntimeOrigin::root(), height.id, false));
+        assert_ok!(Ismp::pause_consensus_client(
+            RuntimeOrigin::root(),
+            MOCK_CONSENSUS_STATE_ID,
+            true
+        ));
+        Pallet::<Test>::handle_messages(vec![request_message(other_height)]).unwrap();
+        assert_eq!(
+            paused_errors(),
+            vec![HandlingError::PausedConsensusClient { id: MOCK_CONSENSUS_STATE_ID }]
+        );
+    })
+}
+
+#[test]
+fn should_reject_reserved_consensus_client_ids() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let create = |consensus_client_id| CreateConsensusState {
+            consensus_state: vec![],
+            consensus_client_id,
+            consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+            unbonding_period: 1_000_000,
+            challenge_period: 0,
+            state_machine_commitments: vec![],
+        };
+
+        assert_noop!(
+            Ismp::create_consensus_client(
+                RuntimeOrigin::root(),
+                create(primitives::INVALID_CONSENSUS_CLIENT_ID)
+            ),
+            Error::<Test>::ConsensusClientCreationFailed
+        );
+        #[cfg(not(feature = "runtime-benchmarks"))]
+        assert_noop!(
+            Ismp::create_consensus_client(
+                RuntimeOrigin::root(),
+                create(primitives::BENCHMARK_CONSENSUS_CLIENT_ID)
+            ),
+            Error::<Test>::ConsensusClientCreationFailed
+        );
+        assert_ok!(Ismp::create_consensus_client(
+            RuntimeOrigin::root(),
+            create(MOCK_CONSENSUS_STATE_ID)
+        ));
+    })
+}
+
+#[test]
+fn should_generate_proof_for_a_range_of_requests() {
+    let mut ext = new_test_ext();
+    let (root, requests) = ext.execute_with(|| {
+        set_timestamp(None);
+        let host = Host::<Test>::default();
+        let requests = (0..4).map(|_| dispatch_post_with_timeout(&host, 0)).collect::<Vec<_>>();
+        new_block();
+        (Pallet::<Test>::mmr_root(), requests)
+    });
+    ext.persist_offchain_overlay();
+
+    register_offchain_ext(&mut ext);
+    ext.execute_with(move || {
+        let dest = StateMachine::Ethereum(Ethereum::ExecutionLayer);
+        let (leaves, proof) = Pallet::<Test>::generate_proof_for_range(0, 10, dest).unwrap();
+        assert_eq!(
+            leaves,
+            requests.into_iter().map(|request| Leaf::Request(request)).collect::<Vec<_>>()
+        );
+
+        let mmr_size = NodesUtils::new(proof.leaf_count).size();
+        let nodes = proof.items.into_iter().map(|h| DataOrHash::Hash(h.into())).collect();
+        let merkle_proof = MerkleProof::<DataOrHash, MmrHasher<Host<Test>>>::new(mmr_size, nodes);
+        let calculated_root = merkle_proof
+            .calculate_root(
+                proof
+                    .leaf_indices
+                    .into_iter()
+                    .zip(leaves.into_iter().map(|leaf| DataOrHash::Data(leaf)))
+                    .collect(),
+            )
+            .unwrap();
+        assert_eq!(root, calculated_root.hash::<Host<Test>>());
+
+        assert_eq!(
+            Pallet::<Test>::generate_proof_for_range(5, 4, dest),
+            Err(primitives::Error::InvalidLeafIndex)
+        );
+        assert_eq!(
+            Pallet::<Test>::generate_proof_for_range(0, 10, StateMachine::Polkadot(1000)),
+            Err(primitives::Error::LeafNotFound)
+        );
+    })
+}
+
+#[test]
+fn should_retain_historical_roots_up_to_the_configured_depth() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        let mut roots = vec![];
+        for nonce in 0..12 {
+            push_leaves(nonce..nonce + 1);
+            new_block();
+            roots.push(Pallet::<Test>::mmr_root());
+        }
+
+        // roots for blocks 1 and 2 have been pruned
+        assert_eq!(Pallet::<Test>::get_historical_root(1), None);
+        assert_eq!(Pallet::<Test>::get_historical_root(2), None);
+        for block in 3..=12u64 {
+            assert_eq!(Pallet::<Test>::get_historical_root(block), Some(roots[block as usize - 1]));
+        }
+
+        // blocks that don't modify the mmr don't record a root, but still prune the history
+        new_block();
+        assert_eq!(Pallet::<Test>::get_historical_root(13), None);
+        assert_eq!(Pallet::<Test>::get_historical_root(3), None);
+        assert_eq!(Pallet::<Test>::mmr_root(), roots[11]);
+    })
+}
+
+#[test]
+fn should_index_leaves_by_block() {
+    let mut ext = new_test_ext();
+    register_offchain_ext(&mut ext);
+    let (first, second, third, first_positions, second_positions, third_positions) = ext
+        .execute_with(|| {
+            let first = frame_system::Pallet::<Test>::block_number();
+            let first_positions = push_leaves(0..3);
+            new_block();
+            let second = frame_system::Pallet::<Test>::block_number();
+            let second_positions = push_leaves(3..4);
+            new_block();
+            // leaves beyond `MaxLeavesPerBlock` are not indexed
+            let third = frame_system::Pallet::<Test>::block_number();
+            let third_positions = push_leaves(4..10);
+            new_block();
+            (first, second, third, first_positions, second_positions, third_positions)
+        });
+    ext.persist_offchain_overlay();
+
+    ext.execute_with(|| {
+        // the index holds mmr positions, not leaf ordinals
+        let leaves = |block| Pallet::<Test>::get_leaves_at_block(block);
+        assert_eq!(leaves(first), BlockLeaves { leaves: vec![0, 1, 3], truncated: false });
+        assert_eq!(leaves(first).leaves, first_positions);
+        assert_eq!(leaves(second), BlockLeaves { leaves: second_positions, truncated: false });
+        // a partial index is flagged as such
+        assert_eq!(
+            leaves(third),
+            BlockLeaves { leaves: third_positions[..4].to_vec(), truncated: true }
+        );
+        assert_eq!(leaves(third + 1), BlockLeaves::default());
+
+        // the positions can be used to fetch the leaves
+        let nonces = Pallet::<Test>::get_requests(leaves(first).leaves)
+            .into_iter()
+            .map(|request| request.nonce())
+            .collect::<Vec<_>>();
+        assert_eq!(nonces, vec![0, 1, 2]);
+
+        for _ in 0..10 {
+            new_block();
+        }
+        assert_eq!(leaves(first), BlockLeaves::default());
+        assert_eq!(leaves(third), BlockLeaves::default());
+    })
+}
+
+#[test]
+fn should_index_outgoing_requests_by_destination() {
+    let mut ext = new_test_ext();
+    let requests = ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let requests = (0..3).map(|_| dispatch_post_with_timeout(&host, 0)).collect::<Vec<_>>();
+        host.delete_request_commitment(&requests[1]).unwrap();
+        requests
+    });
+    ext.persist_offchain_overlay();
+
+    register_offchain_ext(&mut ext);
+    ext.execute_with(|| {
+        let mut pending = Pallet::<Test>::pending_requests_for_dest(StateMachine::Ethereum(
+            Ethereum::ExecutionLayer,
+        ));
+        pending.sort_by_key(|request| request.nonce());
+        assert_eq!(pending, vec![requests[0].clone(), requests[2].clone()]);
+        assert!(Pallet::<Test>::pending_requests_for_dest(StateMachine::Polkadot(1000)).is_empty());
+    })
+}
+
+#[test]
+fn should_record_request_metadata_and_migrate_legacy_commitments() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let request = dispatch_post_with_timeout(&host, 5000);
+        let commitment = hash_request::<Host<Test>>(&request);
+        let query = LeafIndexQuery {
+            source_chain: request.source_chain(),
+            dest_chain: request.dest_chain(),
+            nonce: request.nonce(),
+        };
+        assert_eq!(
+            RequestCommitments::<Test>::get(commitment),
+            Some(primitives::RequestMetadata {
+                query: query.clone(),
+                kind: Some(primitives::RequestKind::Post),
+                timeout_timestamp: Some(5000),
+            })
+        );
+
+        // commitments stored before the metadata was recorded
+        frame_support::storage::unhashed::put(
+            &RequestCommitments::<Test>::hashed_key_for(commitment),
+            &query,
+        );
+        migrations::MigrateRequestCommitments::<Test>::on_runtime_upgrade();
+        assert_eq!(
+            RequestCommitments::<Test>::get(commitment),
+            Some(primitives::RequestMetadata { query, kind: None, timeout_timestamp: None })
+        );
+    })
+}
+
+#[test]
+fn should_simulate_messages_without_persisting_changes() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        setup_mock_client::<_, Test>(&host);
+        set_timestamp(Some(2_000_000));
+        let update_time = ConsensusClientUpdateTime::<Test>::get(MOCK_CONSENSUS_STATE_ID);
+
+        let results = Pallet::<Test>::simulate_messages(vec![
+            Message::Consensus(ConsensusMessage {
+                consensus_proof: vec![],
+                consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+            }),
+            Message::Consensus(ConsensusMessage {
+                consensus_proof: vec![],
+                consensus_state_id: *b"none",
+            }),
+        ]);
+        assert!(results[0].is_ok());
+        // messages go through the same checks as when they are handled
+        assert_eq!(results[1], Err(vec![HandlingError::UnknownConsensusClient { id: *b"none" }]));
+
+        // the successful update was rolled back
+        assert_eq!(ConsensusClientUpdateTime::<Test>::get(MOCK_CONSENSUS_STATE_ID), update_time);
+    })
+}
+
+#[test]
+fn should_cache_state_commitments_for_pending_get_requests() {
+    let mut ext = new_test_ext();
+    register_offchain_ext(&mut ext);
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let msg = DispatchGet {
+            dest: StateMachine::Ethereum(Ethereum::ExecutionLayer),
+            from: vec![0u8; 32],
+            gas_limit: 0,
+            keys: vec![vec![1u8; 32]],
+            height: height.height,
+            timeout_timestamp: 0,
+        };
+        let dispatcher = Dispatcher::<Test>::default();
+        dispatcher.dispatch_request(DispatchRequest::Get(msg)).unwrap();
+        Ismp::on_finalize(1);
+        assert!(BlockGetRequests::<Test>::get().is_empty());
+    });
+    ext.persist_offchain_overlay();
+
+    ext.execute_with(|| {
+        let height = StateMachineHeight {
+            id: StateMachineId {
+                state_id: StateMachine::Ethereum(Ethereum::ExecutionLayer),
+                consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+            },
+            height: 3,
+        };
+        let commitment = StateCommitments::<Test>::get(&height).unwrap();
+        Pallet::<Test>::offchain_worker(1);
+
+        // the cached value is served even once the on-chain commitment is gone
+        StateCommitments::<Test>::remove(&height);
+        assert_eq!(Pallet::<Test>::cached_state_commitment(height.clone()), Some(commitment));
+
+        // the request is still pending in later blocks
+        Pallet::<Test>::offchain_worker(2);
+        assert_eq!(Pallet::<Test>::cached_state_commitment(height.clone()), Some(commitment));
+
+        // the cached value is evicted once the request is answered
+        let get = Pallet::<Test>::pending_get_requests().pop().unwrap();
+        ResponseReceipts::<Test>::insert(
+            hash_request::<Host<Test>>(&Request::Get(get)),
+            Receipt::Ok,
+        );
+        Pallet::<Test>::offchain_worker(3);
+        assert_eq!(Pallet::<Test>::cached_state_commitment(height), None);
+    })
+}
+
+#[test]
+fn should_publish_relayer_hints() {
+    let mut ext = new_test_ext();
+    register_offchain_ext(&mut ext);
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let dispatcher = Dispatcher::<Test>::default();
+        dispatcher
+            .dispatch_request(DispatchRequest::Get(DispatchGet {
+                dest: StateMachine::Ethereum(Ethereum::ExecutionLayer),
+                from: vec![0u8; 32],
+                gas_limit: 0,
+                keys: vec![vec![1u8; 32]],
+                height: height.height,
+                timeout_timestamp: 0,
+            }))
+            .unwrap();
+        dispatcher
+            .dispatch_request(DispatchRequest::Post(DispatchPost {
+                dest: StateMachine::Kusama(2000),
+                from: vec![0u8; 32],
+                to: vec![0u8; 32],
+                timeout_timestamp: 0,
+                data: vec![0u8; 64],
+                gas_limit: 0,
+            }))
+            .unwrap();
+        Ismp::on_finalize(1);
+    });
+    ext.persist_offchain_overlay();
+
+    ext.execute_with(|| {
+        assert_eq!(Pallet::<Test>::relayer_hints(), None);
+        Pallet::<Test>::offchain_worker(1);
+
+        let hints = Pallet::<Test>::relayer_hints().unwrap();
+        assert_eq!(hints.block_number, 1);
+        let pending_requests =
+            [StateMachine::Ethereum(Ethereum::ExecutionLayer), StateMachine::Kusama(2000)]
+                .into_iter()
+                .flat_map(Pallet::<Test>::pending_requests_for_dest)
+                .map(|request| (request.dest_chain(), hash_request::<Host<Test>>(&request)))
+                .collect::<BTreeSet<_>>();
+        assert_eq!(pending_requests.len(), 2);
+        assert_eq!(hints.pending_requests.into_iter().collect::<BTreeSet<_>>(), pending_requests);
+        let pending_get_requests = Pallet::<Test>::pending_get_requests()
+            .into_iter()
+            .map(|get| hash_request::<Host<Test>>(&Request::Get(get)))
+            .collect::<Vec<_>>();
+        assert_eq!(pending_get_requests.len(), 1);
+        assert_eq!(hints.pending_get_requests, pending_get_requests);
+    })
+}
+
+#[test]
+fn should_emit_block_stats_on_finalize() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        frame_system::Pallet::<Test>::set_block_number(1);
+        push_leaves(0..3);
+        let post = Post {
+            source: StateMachine::Kusama(2001),
+            dest: StateMachine::Kusama(2000),
+            nonce: 0,
+            from: vec![0u8; 32],
+            to: vec![1u8; 32],
+            timeout_timestamp: 0,
+            data: vec![2u8; 64],
+            gas_limit: 0,
+        };
+        let response = Response::Post(PostResponse { post, response: vec![1u8; 64] });
+        Pallet::<Test>::mmr_push(Leaf::Response(response)).unwrap();
+
+        Ismp::on_finalize(1);
+        let mmr_root = Pallet::<Test>::mmr_root();
+        assert!(frame_system::Pallet::<Test>::events().into_iter().any(|record| {
+            record.event ==
+                RuntimeEvent::Ismp(Event::BlockStats { requests: 3, responses: 1, mmr_root })
+        }));
+        assert_eq!(BlockMessageCounts::<Test>::get(), (0, 0));
+    })
+}
+
+#[test]
+fn should_timeout_cancelled_requests_once_the_destination_acknowledges_it() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        frame_system::Pallet::<Test>::set_block_number(1);
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let request = dispatch_post_with_timeout(&host, 10_000_000);
+        let commitment = hash_request::<Host<Test>>(&request);
+        let post = match request.clone() {
+            Request::Post(post) => post,
+            _ => unreachable!(),
+        };
+        let dispatcher = Dispatcher::<Test>::default();
+
+        let unknown = Post { nonce: post.nonce + 1, ..post.clone() };
+        assert_eq!(
+            dispatcher.cancel_request(unknown).unwrap_err().kind,
+            primitives::DispatchErrorKind::UnknownRequest
+        );
+        dispatcher.cancel_request(post.clone()).unwrap();
+        assert!(CancelledRequests::<Test>::contains_key(commitment));
+        assert_eq!(
+            dispatcher.cancel_request(post.clone()).unwrap_err().kind,
+            primitives::DispatchErrorKind::Duplicate
+        );
+
+        let cancellation = Post {
+            source: host.host_state_machine(),
+            dest: post.dest,
+            nonce: post.nonce + 1,
+            from: primitives::CANCELLATION_MODULE_ID.0.to_vec(),
+            to: primitives::ENVELOPE_MODULE_ID.0.to_vec(),
+            timeout_timestamp: post.timeout_timestamp,
+            data: primitives::PostEnvelope {
+                to: primitives::CANCELLATION_MODULE_ID.0.to_vec(),
+                ack_requested: true,
+                compressed: false,
+                data: post.encode(),
+            }
+            .to_data(),
+            gas_limit: 0,
+        };
+        assert!(RequestCommitments::<Test>::contains_key(hash_request::<Host<Test>>(
+            &Request::Post(cancellation.clone())
+        )));
+        // the request is still pending until the destination acknowledges the cancellation
+        assert!(RequestCommitments::<Test>::contains_key(commitment));
+
+        let ack = Response::Post(PostResponse {
+            post: cancellation,
+            response: primitives::AckCode::Success.encode(),
+        });
+        Pallet::<Test>::handle_messages(vec![Message::Response(ResponseMessage::Post {
+            responses: vec![ack],
+            proof: Proof { height, proof: vec![] },
+        })])
+        .unwrap();
+
+        assert!(!RequestCommitments::<Test>::contains_key(commitment));
+        assert!(!CancelledRequests::<Test>::contains_key(commitment));
+        assert!(frame_support::storage::unhashed::exists(&mocks::ismp::timeout_key(&request)));
+        assert!(frame_system::Pallet::<Test>::events().into_iter().any(
+            |record| record.event == RuntimeEvent::Ismp(Event::RequestCancelled { commitment })
+        ));
+    })
+}
+
+#[test]
+fn should_reject_forged_cancellations() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        frame_system::Pallet::<Test>::set_block_number(1);
+        let host = Host::<Test>::default();
+        let request = dispatch_post_with_timeout(&host, 10_000_000);
+        let post = match request {
+            Request::Post(post) => post,
+            _ => unreachable!(),
+        };
+        let dispatcher = Dispatcher::<Test>::default();
+        let cancellation = DispatchPost {
+            dest: post.dest,
+            from: primitives::CANCELLATION_MODULE_ID.0.to_vec(),
+            to: primitives::CANCELLATION_MODULE_ID.0.to_vec(),
+            timeout_timestamp: post.timeout_timestamp,
+            data: post.encode(),
+            gas_limit: 0,
+        };
+        let enveloped = primitives::PostEnvelope::seal(cancellation.clone(), true, false);
+        let commitments = RequestCommitments::<Test>::iter_keys().count();
+
+        // modules can't send requests from or to the reserved module ids
+        for forged in [
+            cancellation.clone(),
+            enveloped.clone(),
+            DispatchPost { from: vec![0u8; 32], ..cancellation.clone() },
+            DispatchPost { from: vec![0u8; 32], ..enveloped },
+        ] {
+            let err = dispatcher.try_dispatch_request(DispatchRequest::Post(forged)).unwrap_err();
+            assert_eq!(err.kind, primitives::DispatchErrorKind::ReservedModule);
+        }
+        assert!(dispatcher
+            .dispatch_post_with_ack(DispatchPost { from: vec![0u8; 32], ..cancellation.clone() })
+            .is_err());
+        assert!(dispatcher
+            .dispatch_request(DispatchRequest::Get(DispatchGet {
+                dest: post.dest,
+                from: primitives::CANCELLATION_MODULE_ID.0.to_vec(),
+                keys: vec![vec![1u8; 32]],
+                height: 1,
+                timeout_timestamp: 0,
+                gas_limit: 0,
+            }))
+            .is_err());
+
+        assert_eq!(RequestCommitments::<Test>::iter_keys().count(), commitments);
+        assert!(!CancelledRequests::<Test>::contains_key(hash_request::<Host<Test>>(
+            &Request::Post(post)
+        )));
+    })
+}
+
+#[test]
+fn should_refuse_cancelled_requests_at_their_destination() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let request = |nonce| Post {
+            source: height.id.state_id,
+            dest: host.host_state_machine(),
+            nonce,
+            from: vec![0u8; 32],
+            to: mocks::ismp::MODULE_ID.to_bytes(),
+            timeout_timestamp: 0,
+            data: b"hello".to_vec(),
+            gas_limit: 0,
+        };
+        let cancellation = |nonce, cancelled: &Post| Post {
+            source: height.id.state_id,
+            dest: host.host_state_machine(),
+            nonce,
+            from: primitives::CANCELLATION_MODULE_ID.0.to_vec(),
+            to: primitives::ENVELOPE_MODULE_ID.0.to_vec(),
+            timeout_timestamp: 0,
+            data: primitives::PostEnvelope {
+                to: primitives::CANCELLATION_MODULE_ID.0.to_vec(),
+                ack_requested: true,
+                compressed: false,
+                data: cancelled.encode(),
+            }
+            .to_data(),
+            gas_limit: 0,
+        };
+        let acknowledged = |cancellation: Post, code: primitives::AckCode| {
+            let ack = Response::Post(PostResponse { post: cancellation, response: code.encode() });
+            ResponseCommitments::<Test>::contains_key(hash_response::<Host<Test>>(&ack))
+        };
+        let deliver = |requests| {
+            Pallet::<Test>::execute_messages(vec![Message::Request(RequestMessage {
+                requests,
+                proof: Proof { height: height.clone(), proof: vec![] },
+            })])
+        };
+
+        // a request cancelled before it's delivered can't be delivered anymore
+        let cancelled = request(0);
+        let first = cancellation(1, &cancelled);
+        deliver(vec![first.clone()]);
+        assert!(acknowledged(first, primitives::AckCode::Success));
+        assert!(RequestReceipts::<Test>::contains_key(hash_request::<Host<Test>>(&Request::Post(
+            cancelled
+        ))));
+
+        // a request delivered before its cancellation isn't cancelled
+        let delivered = request(2);
+        deliver(vec![delivered.clone()]);
+        let late = cancellation(3, &delivered);
+        deliver(vec![late.clone()]);
+        assert!(acknowledged(late, primitives::AckCode::Failure));
+
+        // only the source of a request can cancel it
+        let foreign = Post { source: StateMachine::Kusama(2000), ..request(4) };
+        let forged = cancellation(5, &foreign);
+        deliver(vec![forged.clone()]);
+        assert!(acknowledged(forged, primitives::AckCode::Failure));
+        assert!(!RequestReceipts::<Test>::contains_key(hash_request::<Host<Test>>(
+            &Request::Post(foreign)
+        )));
+    })
+}
+
+#[test]
+fn should_only_advance_state_machines_from_their_registered_consensus_state() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        set_timestamp(None);
+        let host = Host::<Test>::default();
+        let state_id = StateMachine::Ethereum(Ethereum::ExecutionLayer);
+        let create = |consensus_state_id| CreateConsensusState {
+            consensus_state: vec![],
+            consensus_client_id: MOCK_CONSENSUS_STATE_ID,
+            consensus_state_id,
+            unbonding_period: 1_000_000,
+            challenge_period: 0,
+            state_machine_commitments: vec![(
+                StateMachineId { state_id, consensus_state_id },
+                StateCommitmentHeight {
+                    commitment: StateCommitment {
+                        timestamp: 1_651_280_681,
+                        overlay_root: None,
+                        state_root: Default::default(),
+                    },
+                    height: 3,
+                },
+            )],
+        };
+        assert_ok!(Ismp::create_consensus_client(RuntimeOrigin::root(), create(*b"mck1")));
+        assert_ok!(Ismp::create_consensus_client(RuntimeOrigin::root(), create(*b"mck2")));
+
+        let height = |consensus_state_id| StateMachineHeight {
+            id: StateMachineId { state_id, consensus_state_id },
+            height: 4,
+        };
+        // nothing is written for a height from a consensus state that isn't registered
+        let commitment = StateCommitment {
+            timestamp: 1_651_280_682,
+            overlay_root: None,
+            state_root: Default::default(),
+        };
+        assert!(host.store_state_machine_commitment(height(*b"mck2"), commitment).is_err());
+        assert!(!StateCommitments::<Test>::contains_key(height(*b"mck2")));
+        assert!(host
+            .store_state_machine_update_time(height(*b"mck2"), Duration::from_secs(1))
+            .is_err());
+        assert!(!StateMachineUpdateTime::<Test>::contains_key(height(*b"mck2")));
+        assert!(host.store_latest_commitment_height(height(*b"mck2")).is_err());
+        assert_eq!(host.latest_commitment_height(height(*b"mck2").id).unwrap(), 3);
+
+        assert_ok!(host.store_latest_commitment_height(height(*b"mck1")));
+        assert_eq!(host.latest_commitment_height(height(*b"mck1").id).unwrap(), 4);
+    })
+}
+
+#[test]
+fn should_report_seeded_heights_when_creating_consensus_clients() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        frame_system::Pallet::<Test>::set_block_number(1);
+        set_timestamp(None);
+        let commitment = StateCommitment {
+            timestamp: 1_651_280_681,
+            overlay_root: None,
+            state_root: Default::default(),
+        };
+        let seeded_heights = [
+            (StateMachine::Ethereum(Ethereum::ExecutionLayer), 3),
+            (StateMachine::Polkadot(1000), 5),
+            (StateMachine::Kusama(2000), 7),
+        ]
+        .into_iter()
+        .map(|(state_id, height)| StateMachineHeight {
+            id: StateMachineId { state_id, consensus_state_id: MOCK_CONSENSUS_STATE_ID },
+            height,
+        })
+        .collect::<Vec<_>>();
+
+        assert_ok!(Ismp::create_consensus_client(
+            RuntimeOrigin::root(),
+            CreateConsensusState {
+                consensus_state: vec![],
+                consensus_client_id: MOCK_CONSENSUS_STATE_ID,
+                consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+                unbonding_period: 1_000_000,
+                challenge_period: 0,
+                state_machine_commitments: seeded_heights
+                    .iter()
+                    .map(|height| {
+                        (
+                            height.id,
+                            StateCommitmentHeight {
+                                commitment: commitment.clone(),
+                                height: height.height,
+                            },
+                        )
+                    })
+                    .collect(),
+            }
+        ));
+
+        frame_system::Pallet::<Test>::assert_last_event(RuntimeEvent::Ismp(
+            Event::ConsensusClientCreated {
+                consensus_client_id: MOCK_CONSENSUS_STATE_ID,
+                seeded_heights: seeded_heights.clone(),
+            },
+        ));
+        for height in seeded_heights {
+            assert_eq!(StateCommitments::<Test>::get(height), Some(commitment.clone()));
+        }
+    })
+}
+
+#[test]
+fn should_reject_requests_proven_by_unregistered_consensus_states() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        set_timestamp(None);
+        let state_id = StateMachine::Ethereum(Ethereum::ExecutionLayer);
+        let create = |consensus_state_id| CreateConsensusState {
+            consensus_state: vec![],
+            consensus_client_id: MOCK_CONSENSUS_STATE_ID,
+            consensus_state_id,
+            unbonding_period: 1_000_000,
+            challenge_period: 0,
+            state_machine_commitments: vec![(
+                StateMachineId { state_id, consensus_state_id },
+                StateCommitmentHeight {
+                    commitment: StateCommitment {
+                        timestamp: 1_651_280_681,
+                        overlay_root: None,
+                        state_root: Default::default(),
+                    },
+                    height: 3,
+                },
+            )],
+        };
+
+        // the first consensus state to track the state machine is its source of truth
+        assert_ok!(Ismp::create_consensus_client(RuntimeOrigin::root(), create(*b"mck1")));
+        assert_ok!(Ismp::create_consensus_client(RuntimeOrigin::root(), create(*b"mck2")));
+        assert_eq!(StateMachineConsensusClient::<Test>::get(state_id), Some(*b"mck1"));
+
+        let request_message = |consensus_state_id| {
+            Message::Request(RequestMessage {
+                requests: vec![],
+                proof: Proof {
+                    height: StateMachineHeight {
+                        id: StateMachineId { state_id, consensus_state_id },
+                        height: 3,
+                    },
+                    proof: vec![],
+                },
+            })
+        };
+
+        let errors = Pallet::<Test>::execute_messages(vec![request_message(*b"mck2")]);
+        assert_eq!(
+            errors,
+            vec![HandlingError::ConsensusClientMismatch {
+                id: StateMachineId { state_id, consensus_state_id: *b"mck2" },
+                registered: *b"mck1",
+            }]
+        );
+        let errors = Pallet::<Test>::execute_messages(vec![request_message(*b"mck1")]);
+        assert!(!errors
+            .iter()
+            .any(|err| matches!(err, HandlingError::ConsensusClientMismatch { .. })));
+    })
+}
+
+#[test]
+fn should_check_proof_shape() {
+    let mut ext = new_test_ext();
+    let positions = ext.execute_with(|| {
+        let positions = push_leaves(0..12);
+        new_block();
+        positions
+    });
+    ext.persist_offchain_overlay();
+
+    register_offchain_ext(&mut ext);
+    ext.execute_with(move || {
+        let (_, proof) =
+            Pallet::<Test>::generate_proof(vec![positions[0], positions[5], positions[11]])
+                .unwrap();
+        assert_eq!(proof.verify_shape(), Ok(()));
+        assert_eq!(proof.clone(), proof);
+
+        let malformed = |f: fn(&mut primitives::Proof<H256>)| {
+            let mut malformed = proof.clone();
+            f(&mut malformed);
+            assert_ne!(malformed, proof);
+            malformed.verify_shape()
+        };
+        assert_eq!(malformed(|p| p.leaf_indices.clear()), Err(primitives::Error::InvalidLeafIndex));
+        assert_eq!(
+            malformed(|p| p.leaf_indices.push(p.leaf_indices[0])),
+            Err(primitives::Error::InvalidLeafIndex)
+        );
+        assert_eq!(
+            malformed(|p| p.leaf_indices.push(u64::MAX)),
+            Err(primitives::Error::InvalidLeafIndex)
+        );
+        assert_eq!(
+            malformed(|p| p.items.extend(vec![H256::zero(); 64])),
+            Err(primitives::Error::InvalidProofShape)
+        );
+
+        let membership_proof = ismp_primitives::MembershipProof {
+            mmr_size: NodesUtils::new(proof.leaf_count).size(),
+            leaf_indices: proof.leaf_indices.clone(),
+            proof: proof.items.clone(),
+        };
+        assert_eq!(membership_proof.clone(), membership_proof);
+    })
+}
+
+#[test]
+fn should_reject_requests_that_were_already_received() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        frame_system::Pallet::<Test>::set_block_number(1);
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let post = Post {
+            source: height.id.state_id,
+            dest: host.host_state_machine(),
+            nonce: 0,
+            from: vec![0u8; 32],
+            to: vec![0u8; 32],
+            timeout_timestamp: 0,
+            data: vec![0u8; 64],
+            gas_limit: 0,
+        };
+        let messages = || {
+            vec![Message::Request(RequestMessage {
+                requests: vec![post.clone()],
+                proof: Proof { height: height.clone(), proof: vec![] },
+            })]
+        };
+        let handling_errors = || {
+            frame_system::Pallet::<Test>::events().into_iter().find_map(|record| {
+                match record.event {
+                    RuntimeEvent::Ismp(Event::HandlingErrors { errors }) => Some(errors),
+                    _ => None,
+                }
+            })
+        };
+
+        assert_ok!(Pallet::<Test>::handle_messages(messages()));
+        assert_eq!(handling_errors(), None);
+        assert_eq!(RequestReceipts::<Test>::iter().count(), 1);
+
+        frame_system::Pallet::<Test>::reset_events();
+        assert_ok!(Pallet::<Test>::handle_messages(messages()));
+        assert_eq!(handling_errors().map(|errors| errors.len()), Some(1));
+        assert_eq!(RequestReceipts::<Test>::iter().count(), 1);
+        assert!(RequestReceipts::<Test>::contains_key(hash_request::<Host<Test>>(&Request::Post(
+            post
+        ))));
+    })
+}
+
+#[test]
+fn should_only_charge_for_the_weight_consumed_by_callbacks() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let request = |nonce| Post {
+            source: height.id.state_id,
+            dest: host.host_state_machine(),
+            nonce,
+            from: vec![0u8; 32],
+            to: CALLBACK_MODULE_ID.to_bytes(),
+            timeout_timestamp: 0,
+            data: vec![],
+            gas_limit: 0,
+        };
+        let messages = vec![Message::Request(RequestMessage {
+            requests: vec![request(0), request(1)],
+            proof: Proof { height: height.clone(), proof: vec![] },
+        })];
+        let declared = weight_info::get_weight::<Test>(&messages);
+        assert!(declared.all_gte(CALLBACK_WEIGHT_LIMIT * 2));
+
+        let origin = RuntimeOrigin::signed(sp_core::sr25519::Public::from_raw([0u8; 32]));
+        let info = Ismp::handle(origin.clone(), messages).unwrap();
+        assert_eq!(
+            info.actual_weight,
+            Some(declared - CALLBACK_WEIGHT_LIMIT * 2 + CALLBACK_WEIGHT_USED * 2)
+        );
+        assert_eq!(RequestReceipts::<Test>::iter().count(), 2);
+
+        // the consumed weight is reset for every transaction
+        let messages = vec![Message::Request(RequestMessage {
+            requests: vec![request(2)],
+            proof: Proof { height, proof: vec![] },
+        })];
+        let declared = weight_info::get_weight::<Test>(&messages);
+        let info = Ismp::handle(origin, messages).unwrap();
+        assert_eq!(
+            info.actual_weight,
+            Some(declared - CALLBACK_WEIGHT_LIMIT + CALLBACK_WEIGHT_USED)
+        );
+    })
+}
+
+#[test]
+fn should_try_tuple_consensus_client_providers_in_order() {
+    struct Provider<const ID: u8>;
+
+    impl<const ID: u8> primitives::ConsensusClientProvider for Provider<ID> {
+        fn consensus_client(
+            id: ConsensusClientId,
+        ) -> Result<Box<dyn ismp_rs::consensus::ConsensusClient>, ismp_rs::error::Error> {
+            if id != [ID; 4] {
+                Err(ismp_rs::error::Error::ImplementationSpecific("Unknown client".into()))?
+            }
+            Ok(Box::new(mocks::ismp::MockConsensusClient))
+        }
+    }
+
+    type Providers = (Provider<1>, Provider<2>);
+    assert!(Providers::consensus_client([1; 4]).is_ok());
+    assert!(Providers::consensus_client([2; 4]).is_ok());
+    assert!(Providers::consensus_client([3; 4]).is_err());
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn should_commit_to_compressed_request_data() {
+    let data = vec![7u8; 4096];
+    let mut ext = new_test_ext();
+    register_offchain_ext(&mut ext);
+    ext.execute_with(|| {
+        let post = DispatchPost {
+            dest: StateMachine::Ethereum(Ethereum::ExecutionLayer),
+            from: vec![0u8; 32],
+            to: vec![0u8; 32],
+            timeout_timestamp: 0,
+            data: data.clone(),
+            gas_limit: 0,
+        };
+        Dispatcher::<Test>::default().dispatch_compressed_post(post).unwrap();
+    });
+    ext.persist_offchain_overlay();
+
+    ext.execute_with(|| {
+        let leaf_index = Pallet::<Test>::get_leaf_index(
+            StateMachine::Kusama(100),
+            StateMachine::Ethereum(Ethereum::ExecutionLayer),
+            0,
+            true,
+        )
+        .unwrap();
+        let request = Pallet::<Test>::get_request(leaf_index).unwrap();
+        assert!(RequestCommitments::<Test>::contains_key(hash_request::<Host<Test>>(&request)));
+
+        let post = match request {
+            Request::Post(post) => post,
+            _ => unreachable!(),
+        };
+        assert_eq!(post.to, primitives::ENVELOPE_MODULE_ID.0.to_vec());
+        let envelope = primitives::PostEnvelope::from_data(&post.data).unwrap();
+        assert!(envelope.compressed);
+        assert!(envelope.data.len() < data.len());
+        assert_eq!(compression::decompress(&envelope.data).unwrap(), data);
+
+        // modules are passed the decompressed data in every callback
+        let (opened, _) = primitives::PostEnvelope::open(post).unwrap();
+        assert_eq!(opened.data, data);
+        assert_eq!(opened.to, vec![0u8; 32]);
+    })
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn should_decompress_enveloped_requests_before_delivering_them() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let post = Post {
+            source: height.id.state_id,
+            dest: host.host_state_machine(),
+            nonce: 0,
+            from: vec![0u8; 32],
+            to: primitives::ENVELOPE_MODULE_ID.0.to_vec(),
+            timeout_timestamp: 0,
+            data: primitives::PostEnvelope {
+                to: mocks::ismp::MODULE_ID.to_bytes(),
+                ack_requested: true,
+                compressed: true,
+                data: compression::compress(mocks::ismp::REJECTED_REQUEST_DATA),
+            }
+            .to_data(),
+            gas_limit: 0,
+        };
+        let messages = vec![Message::Request(RequestMessage {
+            requests: vec![post.clone()],
+            proof: Proof { height, proof: vec![] },
+        })];
+        let origin = RuntimeOrigin::signed(sp_core::sr25519::Public::from_raw([0u8; 32]));
+        assert_ok!(Ismp::handle(origin, messages));
+
+        // the module saw the decompressed data and rejected it
+        let ack =
+            Response::Post(PostResponse { post, response: primitives::AckCode::Failure.encode() });
+        assert!(ResponseCommitments::<Test>::contains_key(hash_response::<Host<Test>>(&ack)));
+    })
+}
+
+#[cfg(feature = "xcm")]
+#[test]
+fn should_pass_xcm_messages_over_ismp() {
+    use crate::mocks::ismp::{MockXcmModule, XCM_EXECUTED_KEY};
+    use ismp_rs::module::IsmpModule;
+    use xcm::latest::{
+        send_xcm, Instruction::ClearOrigin, Junction::Parachain, Junctions::X1, MultiLocation,
+        SendError, SendXcm, Xcm,
+    };
+
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        let message = Xcm::<()>(vec![ClearOrigin]);
+        let sibling = MultiLocation::new(1, X1(Parachain(2000)));
+
+        // only sibling parachains can be reached through the bridge
+        assert!(matches!(
+            send_xcm::<xcm_bridge::XcmIsmpBridge<Test>>(MultiLocation::parent(), message.clone()),
+            Err(SendError::NotApplicable)
+        ));
+
+        let (post, _) = xcm_bridge::XcmIsmpBridge::<Test>::validate(
+            &mut Some(sibling),
+            &mut Some(message.clone()),
+        )
+        .unwrap();
+        send_xcm::<xcm_bridge::XcmIsmpBridge<Test>>(sibling, message.clone()).unwrap();
+        let dispatched = Post {
+            source: StateMachine::Kusama(100),
+            dest: StateMachine::Kusama(2000),
+            nonce: 0,
+            from: post.from,
+            to: post.to,
+            timeout_timestamp: 0,
+            data: post.data,
+            gas_limit: 0,
+        };
+        assert!(RequestCommitments::<Test>::contains_key(hash_request::<Host<Test>>(
+            &Request::Post(dispatched.clone())
+        )));
+
+        // messages from any other module on the sibling parachain are rejected
+        let forged = Post { from: vec![0u8; 32], ..dispatched.clone() };
+        assert!(MockXcmModule::default().on_accept(forged).is_err());
+        assert!(frame_support::storage::unhashed::get_raw(XCM_EXECUTED_KEY).is_none());
+
+        // the receiving parachain executes the message with the sender as origin
+        MockXcmModule::default().on_accept(dispatched).unwrap();
+        assert_eq!(
+            frame_support::storage::unhashed::get::<(MultiLocation, Xcm<()>)>(XCM_EXECUTED_KEY),
+            Some((MultiLocation::new(1, X1(Parachain(100))), message))
+        );
+    })
+}
+
+#[test]
+fn default_weight_provider_should_estimate_module_callbacks() {
+    let post = Post {
+        source: StateMachine::Kusama(2000),
+        dest: StateMachine::Kusama(100),
+        nonce: 0,
+        from: vec![0u8; 32],
+        to: vec![1u8; 32],
+        timeout_timestamp: 0,
+        data: vec![],
+        gas_limit: 0,
+    };
+    let expected = <Test as frame_system::Config>::DbWeight::get().reads_writes(2, 2);
+    let module = primitives::ModuleId::from_bytes(&post.to).unwrap();
+    let weight = weight_info::DefaultWeightProvider::<Test>::module_callback(module).unwrap();
+
+    assert_eq!(weight.on_accept(&post), expected);
+    assert_eq!(weight.on_timeout(&Request::Post(post.clone())), expected);
+    assert!(weight_info::DefaultWeightProvider::<Test>::consensus_client(MOCK_CONSENSUS_STATE_ID)
+        .is_none());
+}
+
+#[test]
+fn should_reject_module_ids_of_unknown_length() {
+    let err = primitives::ModuleId::from_bytes(&[0u8; 24]).unwrap_err();
+    assert_eq!(err, primitives::ModuleIdError::InvalidLength(24));
+    let expected = "Unknown module id format of 24 bytes, expected a pallet id (8 bytes), an evm \
+                    address (20 bytes) or a contract account (32 bytes)";
+    assert_eq!(err.to_string(), expected);
+    assert!(matches!(
+        ismp_rs::error::Error::from(err),
+        ismp_rs::error::Error::ImplementationSpecific(msg) if msg == expected
+    ));
+}
+
+#[test]
+fn should_reject_filtered_messages() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let consensus_message = Message::Consensus(ConsensusMessage {
+            consensus_proof: vec![],
+            consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+        });
+        let request_message = Message::Request(RequestMessage {
+            requests: vec![],
+            proof: Proof { height, proof: vec![] },
+        });
+        assert!(primitives::AcceptAll::filter(&consensus_message));
+        assert!(!primitives::AcceptNoConsensus::filter(&consensus_message));
+        assert!(primitives::AcceptNoConsensus::filter(&request_message));
+
+        frame_support::storage::unhashed::put(FILTER_CONSENSUS_KEY, &true);
+        let errors = Pallet::<Test>::execute_messages(vec![consensus_message, request_message]);
+        assert_eq!(errors, vec![HandlingError::FilteredMessage]);
+    })
+}
+
+#[test]
+fn should_freeze_consensus_client_on_conflicting_state_commitments() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        frame_system::Pallet::<Test>::set_block_number(1);
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let commitment = host.state_machine_commitment(height.clone()).unwrap();
+
+        // storing the same commitment again is allowed
+        host.store_state_machine_commitment(height.clone(), commitment.clone()).unwrap();
+        assert!(host.is_consensus_client_frozen(MOCK_CONSENSUS_STATE_ID).is_ok());
+
+        // the freeze isn't reported as an error, so a transactional batch doesn't roll it back
+        let conflicting =
+            StateCommitment { state_root: H256::repeat_byte(9), ..commitment.clone() };
+        assert_ok!(host.store_state_machine_commitment(height.clone(), conflicting));
+        assert!(host.is_consensus_client_frozen(MOCK_CONSENSUS_STATE_ID).is_err());
+        assert_eq!(StateCommitments::<Test>::get(&height), Some(commitment));
+        frame_system::Pallet::<Test>::assert_last_event(RuntimeEvent::Ismp(
+            Event::ConsensusClientFrozen {
+                consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+                height: height.clone(),
+            },
+        ));
+    })
+}
+
+#[test]
+fn should_read_keys_at_multiple_heights() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        // also track the state machine at height 4
+        let height_4 = StateMachineHeight { id: height.id, height: 4 };
+        host.store_state_machine_commitment(
+            height_4.clone(),
+            host.state_machine_commitment(height.clone()).unwrap(),
+        )
+        .unwrap();
+        host.store_state_machine_update_time(height_4.clone(), Duration::from_secs(1000)).unwrap();
+
+        Dispatcher::<Test>::default()
+            .dispatch_get_multi(dispatcher::DispatchGetMulti {
+                dest: height.id.state_id,
+                from: vec![0u8; 32],
+                keys: vec![vec![1u8; 32]],
+                heights: vec![4, 3, 4],
+                timeout_timestamp: 0,
+                gas_limit: 0,
+            })
+            .unwrap();
+        assert_eq!(RequestCommitments::<Test>::iter().count(), 2);
+
+        let get = |nonce, height| {
+            Request::Get(ismp_rs::router::Get {
+                source: host.host_state_machine(),
+                dest: StateMachine::Ethereum(Ethereum::ExecutionLayer),
+                nonce,
+                from: vec![0u8; 32],
+                keys: vec![vec![1u8; 32]],
+                height,
+                timeout_timestamp: 0,
+                gas_limit: 0,
+            })
+        };
+        // one request per height, in ascending order
+        let requests = vec![get(0, 3), get(1, 4)];
+        for request in &requests {
+            assert!(host.request_commitment(hash_request::<Host<Test>>(request)).is_ok());
+        }
+
+        // the mock proves every key with the proof bytes as its value
+        for (request, proof_height) in requests.iter().zip([height, height_4]) {
+            let value = vec![proof_height.height as u8];
+            let response = ResponseMessage::Get {
+                requests: vec![request.clone()],
+                proof: Proof { height: proof_height, proof: value },
+            };
+            Pallet::<Test>::handle_messages(vec![Message::Response(response)]).unwrap();
+        }
+
+        for (request, value) in requests.iter().zip([3u8, 4]) {
+            let values: BTreeMap<Vec<u8>, Option<Vec<u8>>> =
+                frame_support::storage::unhashed::get(&mocks::ismp::get_response_key(request))
+                    .unwrap();
+            assert_eq!(values.get(&vec![1u8; 32]), Some(&Some(vec![value])));
+        }
+    })
+}
+
+#[test]
+fn should_display_state_machine_ids() {
+    let id =
+        StateMachineId { state_id: StateMachine::Polkadot(1000), consensus_state_id: *b"PARA" };
+    assert_eq!(events::DisplayStateMachineId(&id).to_string(), "StateMachine::Polkadot(1000)/PARA");
+
+    let err = errors::HandlingError::PausedStateMachine { id };
+    assert_eq!(err.to_string(), "State machine StateMachine::Polkadot(1000)/PARA is paused");
+}
+
+#[test]
+fn should_migrate_storage_from_v0_to_v1() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        use frame_support::traits::{GetStorageVersion, StorageVersion};
+
+        StorageVersion::new(0).put::<Pallet<Test>>();
+        let query = LeafIndexQuery {
+            source_chain: StateMachine::Kusama(100),
+            dest_chain: StateMachine::Kusama(2000),
+            nonce: 0,
+        };
+        let commitment = H256::repeat_byte(1);
+        frame_support::storage::unhashed::put(
+            &RequestCommitments::<Test>::hashed_key_for(commitment),
+            &query,
+        );
+
+        <Pallet<Test> as OnRuntimeUpgrade>::on_runtime_upgrade();
+        assert_eq!(
+            Pallet::<Test>::on_chain_storage_version(),
+            Pallet::<Test>::current_storage_version()
+        );
+        assert_eq!(
+            RequestCommitments::<Test>::get(commitment),
+            Some(primitives::RequestMetadata {
+                query: query.clone(),
+                kind: None,
+                timeout_timestamp: None
+            })
+        );
+
+        // running the upgrade again is a no-op
+        <Pallet<Test> as OnRuntimeUpgrade>::on_runtime_upgrade();
+        assert_eq!(
+            RequestCommitments::<Test>::get(commitment),
+            Some(primitives::RequestMetadata { query, kind: None, timeout_timestamp: None })
+        );
+    })
+}
+
+#[test]
+fn should_migrate_update_times_to_milliseconds() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        use frame_support::traits::{GetStorageVersion, StorageVersion};
+
+        StorageVersion::new(1).put::<Pallet<Test>>();
+        ConsensusClientUpdateTime::<Test>::insert(MOCK_CONSENSUS_STATE_ID, 1100);
+
+        <Pallet<Test> as OnRuntimeUpgrade>::on_runtime_upgrade();
+        assert_eq!(
+            Pallet::<Test>::on_chain_storage_version(),
+            Pallet::<Test>::current_storage_version()
+        );
+        assert_eq!(
+            ConsensusClientUpdateTime::<Test>::get(MOCK_CONSENSUS_STATE_ID),
+            Some(1_100_000)
+        );
+        let host = Host::<Test>::default();
+        assert_eq!(
+            host.consensus_update_time(MOCK_CONSENSUS_STATE_ID).unwrap(),
+            core::time::Duration::from_secs(1100)
+        );
+
+        // running the upgrade again is a no-op
+        <Pallet<Test> as OnRuntimeUpgrade>::on_runtime_upgrade();
+        assert_eq!(
+            ConsensusClientUpdateTime::<Test>::get(MOCK_CONSENSUS_STATE_ID),
+            Some(1_100_000)
+        );
+    })
+}
+
+#[test]
+fn should_migrate_consensus_states_to_versioned_encoding() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        use frame_support::traits::{GetStorageVersion, StorageVersion};
+
+        StorageVersion::new(2).put::<Pallet<Test>>();
+        // a legacy state whose first byte happens to match the version byte
+        let legacy = vec![primitives::CONSENSUS_STATE_VERSION, 8, 9];
+        ConsensusStates::<Test>::insert(MOCK_CONSENSUS_STATE_ID, legacy.clone());
+
+        <Pallet<Test> as OnRuntimeUpgrade>::on_runtime_upgrade();
+        assert_eq!(
+            Pallet::<Test>::on_chain_storage_version(),
+            Pallet::<Test>::current_storage_version()
+        );
+        let host = Host::<Test>::default();
+        assert_eq!(host.consensus_state(MOCK_CONSENSUS_STATE_ID).unwrap(), legacy);
+
+        // running the upgrade again is a no-op
+        <Pallet<Test> as OnRuntimeUpgrade>::on_runtime_upgrade();
+        assert_eq!(host.consensus_state(MOCK_CONSENSUS_STATE_ID).unwrap(), legacy);
+    })
+}
+
+#[test]
+fn should_migrate_pending_consensus_updates_to_timestamped_entries() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        use frame_support::traits::{GetStorageVersion, StorageVersion};
+
+        StorageVersion::new(3).put::<Pallet<Test>>();
+        ConsensusClientUpdateTime::<Test>::insert(MOCK_CONSENSUS_STATE_ID, 1_100_000);
+        let id = StateMachineId {
+            state_id: StateMachine::Kusama(2000),
+            consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+        };
+        let (previous, latest) =
+            (StateMachineHeight { id, height: 1 }, StateMachineHeight { id, height: 2 });
+        frame_support::storage::unhashed::put(
+            &ConsensusUpdateResults::<Test>::hashed_key_for(MOCK_CONSENSUS_STATE_ID),
+            &BTreeSet::from([(previous.clone(), latest.clone())]),
+        );
+
+        <Pallet<Test> as OnRuntimeUpgrade>::on_runtime_upgrade();
+        assert_eq!(
+            Pallet::<Test>::on_chain_storage_version(),
+            Pallet::<Test>::current_storage_version()
+        );
+        assert_eq!(
+            ConsensusUpdateResults::<Test>::get(MOCK_CONSENSUS_STATE_ID),
+            Some(BTreeSet::from([(1_100_000, previous, latest)]))
+        );
+    })
+}
+
+#[test]
+fn should_store_update_times_in_milliseconds() {
+    let mut ext = new_test_ext();
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let timestamp = core::time::Duration::from_millis(1_100_250);
+        host.store_consensus_update_time(MOCK_CONSENSUS_STATE_ID, timestamp).unwrap();
+        assert_eq!(host.consensus_update_time(MOCK_CONSENSUS_STATE_ID).unwrap(), timestamp);
+        assert_eq!(Pallet::<Test>::get_consensus_update_time(MOCK_CONSENSUS_STATE_ID), Some(1100));
+    })
+}
+
+#[test]
+fn call_indices_should_be_stable() {
+    assert!(migrations::call_indices_unchanged::<Test>());
+    let call = Call::<Test>::handle { messages: vec![] };
+    assert_eq!(call.encode()[0], ismp_primitives::call_indices::HANDLE);
+}
+
+#[test]
+fn should_acknowledge_requests_that_ask_for_it() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let request = |nonce, data: &[u8]| Post {
+            source: height.id.state_id,
+            dest: host.host_state_machine(),
+            nonce,
+            from: vec![0u8; 32],
+            to: primitives::ENVELOPE_MODULE_ID.0.to_vec(),
+            timeout_timestamp: 0,
+            data: primitives::PostEnvelope {
+                to: mocks::ismp::MODULE_ID.to_bytes(),
+                ack_requested: true,
+                compressed: false,
+                data: data.to_vec(),
+            }
+            .to_data(),
+            gas_limit: 0,
+        };
+        let accepted = request(0, b"hello");
+        let rejected = request(1, mocks::ismp::REJECTED_REQUEST_DATA);
+        let messages = vec![Message::Request(RequestMessage {
+            requests: vec![accepted.clone(), rejected.clone()],
+            proof: Proof { height, proof: vec![] },
+        })];
+        let origin = RuntimeOrigin::signed(sp_core::sr25519::Public::from_raw([0u8; 32]));
+        assert_ok!(Ismp::handle(origin, messages));
+
+        for (post, code) in
+            [(accepted, primitives::AckCode::Success), (rejected, primitives::AckCode::Failure)]
+        {
+            let ack = Response::Post(PostResponse { post, response: code.encode() });
+            assert!(ResponseCommitments::<Test>::contains_key(hash_response::<Host<Test>>(&ack)));
+        }
+    })
+}
+
+#[test]
+fn should_not_acknowledge_requests_twice() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        setup_mock_client::<_, Test>(&host);
+        let post = Post {
+            source: StateMachine::Kusama(2000),
+            dest: host.host_state_machine(),
+            nonce: 0,
+            from: vec![0u8; 32],
+            to: mocks::ismp::MODULE_ID.to_bytes(),
+            timeout_timestamp: 0,
+            data: vec![],
+            gas_limit: 0,
+        };
+
+        assert_ok!(Pallet::<Test>::dispatch_ack(post.clone(), primitives::AckCode::Success));
+        assert_ok!(Pallet::<Test>::dispatch_ack(post.clone(), primitives::AckCode::Failure));
+
+        let ack = Response::Post(PostResponse {
+            post: post.clone(),
+            response: primitives::AckCode::Failure.encode(),
+        });
+        assert!(!ResponseCommitments::<Test>::contains_key(hash_response::<Host<Test>>(&ack)));
+
+        // the module can't respond once the request has been acknowledged
+        RequestReceipts::<Test>::insert(
+            hash_request::<Host<Test>>(&Request::Post(post.clone())),
+            Receipt::Ok,
+        );
+        let response = Response::Post(PostResponse { post, response: b"response".to_vec() });
+        assert_eq!(
+            Pallet::<Test>::dispatch_response(response).unwrap_err().kind,
+            primitives::DispatchErrorKind::Duplicate
+        );
+    })
+}
+
+#[test]
+fn should_only_open_envelopes_of_posts_to_the_envelope_module() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        // data of a foreign post that happens to decode as an envelope
+        let post = Post {
+            source: height.id.state_id,
+            dest: host.host_state_machine(),
+            nonce: 0,
+            from: vec![0u8; 32],
+            to: mocks::ismp::MODULE_ID.to_bytes(),
+            timeout_timestamp: 0,
+            data: primitives::PostEnvelope {
+                to: mocks::ismp::MODULE_ID.to_bytes(),
+                ack_requested: true,
+                compressed: false,
+                data: mocks::ismp::REJECTED_REQUEST_DATA.to_vec(),
+            }
+            .to_data(),
+            gas_limit: 0,
+        };
+        let messages = vec![Message::Request(RequestMessage {
+            requests: vec![post.clone()],
+            proof: Proof { height, proof: vec![] },
+        })];
+        let origin = RuntimeOrigin::signed(sp_core::sr25519::Public::from_raw([0u8; 32]));
+        assert_ok!(Ismp::handle(origin, messages));
+
+        let commitment = hash_request::<Host<Test>>(&Request::Post(post));
+        assert!(RequestReceipts::<Test>::contains_key(commitment));
+        assert!(!RespondedRequests::<Test>::contains_key(commitment));
+    })
+}
+
+#[test]
+fn should_reject_unsupported_post_envelopes() {
+    let envelope = primitives::PostEnvelope {
+        to: mocks::ismp::MODULE_ID.to_bytes(),
+        ack_requested: true,
+        compressed: false,
+        data: b"hello".to_vec(),
+    };
+    let data = envelope.to_data();
+    assert_eq!(primitives::PostEnvelope::from_data(&data).unwrap(), envelope);
+
+    let mut unsupported = data.clone();
+    unsupported[0] = primitives::POST_ENVELOPE_VERSION + 1;
+    assert!(primitives::PostEnvelope::from_data(&unsupported).is_err());
+    assert!(primitives::PostEnvelope::from_data(&data[..data.len() - 1]).is_err());
+
+    // envelopes can't be nested
+    let post = Post {
+        source: StateMachine::Kusama(2000),
+        dest: StateMachine::Kusama(100),
+        nonce: 0,
+        from: vec![0u8; 32],
+        to: primitives::ENVELOPE_MODULE_ID.0.to_vec(),
+        timeout_timestamp: 0,
+        data: primitives::PostEnvelope {
+            to: primitives::ENVELOPE_MODULE_ID.0.to_vec(),
+            ..envelope
+        }
+        .to_data(),
+        gas_limit: 0,
+    };
+    assert!(primitives::PostEnvelope::open(post).is_err());
+}
+
+#[test]
+fn should_batch_query_consensus_states() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        setup_mock_client::<_, Test>(&host);
+
+        let states = Pallet::<Test>::get_consensus_states(vec![*b"none", MOCK_CONSENSUS_STATE_ID]);
+        assert_eq!(
+            states,
+            vec![None, Pallet::<Test>::get_consensus_state(MOCK_CONSENSUS_STATE_ID)]
+        );
+        assert!(states[1].is_some());
+    })
+}
+
+#[test]
+fn should_batch_query_state_commitments() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let unknown = StateMachineHeight { id: height.id, height: height.height + 1 };
+
+        let commitments = Pallet::<Test>::get_state_commitments(vec![height.clone(), unknown]);
+        assert_eq!(commitments, vec![StateCommitments::<Test>::get(height), None]);
+        assert!(commitments[0].is_some());
+    })
+}
+
+#[test]
+fn host_should_batch_read_state_commitments() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let commitment = StateCommitments::<Test>::get(&height).unwrap();
+        let unknown = |offset| StateMachineHeight { id: height.id, height: height.height + offset };
+
+        assert_eq!(
+            host.state_machine_commitments(vec![
+                unknown(1),
+                height.clone(),
+                unknown(2),
+                height.clone()
+            ]),
+            vec![None, Some(commitment.clone()), None, Some(commitment.clone())]
+        );
+        assert!(host.state_machine_commitments(vec![]).is_empty());
+
+        // reads are served from the host's cache once a commitment has been read
+        StateCommitments::<Test>::remove(&height);
+        assert_eq!(host.state_machine_commitments(vec![height]), vec![Some(commitment)]);
+    })
+}
+
+#[test]
+fn should_reject_oversized_consensus_states() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let max = <Test as Config>::MaxConsensusStateBytes::get() as usize;
+
+        assert!(host.store_consensus_state(*b"mck2", vec![0u8; max]).is_ok());
+        assert!(host.store_consensus_state(*b"mck3", vec![0u8; max + 1]).is_err());
+        assert!(ConsensusStates::<Test>::get(*b"mck3").is_none());
+
+        let create = CreateConsensusState {
+            consensus_state: vec![0u8; max + 1],
+            consensus_client_id: MOCK_CONSENSUS_STATE_ID,
+            consensus_state_id: *b"mck4",
+            unbonding_period: 1_000_000,
+            challenge_period: 0,
+            state_machine_commitments: vec![],
+        };
+        assert_noop!(
+            Ismp::create_consensus_client(RuntimeOrigin::root(), create),
+            Error::<Test>::ConsensusClientCreationFailed
+        );
+    })
+}
+
+#[test]
+fn should_only_allow_permitted_relayers() {
+    use sp_runtime::traits::SignedExtension;
+
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let allowed = sp_core::sr25519::Public::from_raw([1u8; 32]);
+        let other = sp_core::sr25519::Public::from_raw([2u8; 32]);
+        let extension = extensions::CheckIsmpPermissions::<Test>::new();
+        let handle = RuntimeCall::Ismp(Call::handle { messages: vec![] });
+        let set_paused = RuntimeCall::Ismp(Call::set_paused { paused: false });
+        let info = Default::default();
+
+        // permissionless while no relayers are allowed
+        assert_ok!(extension.validate(&other, &handle, &info, 0));
+
+        assert_ok!(Ismp::add_relayer(RuntimeOrigin::root(), allowed));
+        assert_ok!(extension.validate(&allowed, &handle, &info, 0));
+        assert_eq!(
+            extension.validate(&other, &handle, &info, 0),
+            Err(InvalidTransaction::BadSigner.into())
+        );
+        // other calls aren't restricted
+        assert_ok!(extension.validate(&other, &set_paused, &info, 0));
+
+        assert_ok!(Ismp::remove_relayer(RuntimeOrigin::root(), allowed));
+        assert_ok!(extension.validate(&other, &handle, &info, 0));
+    })
+}
+
+#[test]
+fn should_apply_consensus_messages_before_requests() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let new_height = StateMachineHeight { id: height.id, height: height.height + 1 };
+        let commitments = BTreeMap::from([(
+            height.id.state_id,
+            vec![StateCommitmentHeight {
+                commitment: StateCommitment {
+                    timestamp: 1_000_000,
+                    overlay_root: None,
+                    state_root: Default::default(),
+                },
+                height: new_height.height,
+            }],
+        )]);
+        let request = Post {
+            source: height.id.state_id,
+            dest: host.host_state_machine(),
+            nonce: 0,
+            from: vec![0u8; 32],
+            to: vec![0u8; 32],
+            timeout_timestamp: 0,
+            data: vec![],
+            gas_limit: 0,
+        };
+
+        // the request is proven against the height introduced by the consensus update after it
+        let messages = vec![
+            Message::Request(RequestMessage {
+                requests: vec![request.clone()],
+                proof: Proof { height: new_height, proof: vec![] },
+            }),
+            Message::Consensus(ConsensusMessage {
+                consensus_proof: commitments.encode(),
+                consensus_state_id: MOCK_CONSENSUS_STATE_ID,
+            }),
+        ];
+        assert!(Pallet::<Test>::execute_messages(messages).is_empty());
+        assert!(RequestReceipts::<Test>::contains_key(hash_request::<Host<Test>>(&Request::Post(
+            request
+        ))));
+    })
+}
+
+#[test]
+fn should_set_consensus_client_names() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let name: frame_support::BoundedVec<u8, frame_support::traits::ConstU32<32>> =
+            b"Mock".to_vec().try_into().unwrap();
+        assert_noop!(
+            Ismp::set_consensus_client_name(
+                RuntimeOrigin::signed(sp_core::sr25519::Public::from_raw([0u8; 32])),
+                MOCK_CONSENSUS_STATE_ID,
+                name.clone()
+            ),
+            sp_runtime::DispatchError::BadOrigin
+        );
+        assert_eq!(Pallet::<Test>::get_consensus_client_name(MOCK_CONSENSUS_STATE_ID), None);
+
+        assert_ok!(Ismp::set_consensus_client_name(
+            RuntimeOrigin::root(),
+            MOCK_CONSENSUS_STATE_ID,
+            name
+        ));
+        assert_eq!(
+            Pallet::<Test>::get_consensus_client_name(MOCK_CONSENSUS_STATE_ID),
+            Some(b"Mock".to_vec())
+        );
+    })
+}
+
+#[test]
+fn should_return_configured_challenge_periods() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        setup_mock_client::<_, Test>(&host);
+        assert_eq!(Pallet::<Test>::get_challenge_period(MOCK_CONSENSUS_STATE_ID), Some(0));
+
+        host.store_challenge_period(MOCK_CONSENSUS_STATE_ID, 600).unwrap();
+        assert_eq!(Pallet::<Test>::get_challenge_period(MOCK_CONSENSUS_STATE_ID), Some(600));
+        assert_eq!(Pallet::<Test>::get_challenge_period(*b"none"), None);
+    })
+}
+
+#[test]
+fn should_reject_oversized_timeout_batches() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        setup_mock_client::<_, Test>(&host);
+        let max = <Test as Config>::MaxTimeoutBatchSize::get();
+        let timeout = |size: u32| {
+            let requests = (0..size as u64)
+                .map(|nonce| {
+                    Request::Get(ismp_rs::router::Get {
+                        source: host.host_state_machine(),
+                        dest: StateMachine::Ethereum(Ethereum::ExecutionLayer),
+                        nonce,
+                        from: vec![0u8; 32],
+                        keys: vec![vec![1u8; 32]],
+                        height: 2,
+                        timeout_timestamp: 1000,
+                        gas_limit: 0,
+                    })
+                })
+                .collect();
+            Message::Timeout(TimeoutMessage::Get { requests })
+        };
+
+        let errors = Pallet::<Test>::execute_messages(vec![timeout(max)]);
+        assert!(!errors
+            .iter()
+            .any(|err| matches!(err, errors::HandlingError::BatchTooLarge { .. })));
+
+        let errors = Pallet::<Test>::execute_messages(vec![timeout(max + 1)]);
+        assert_eq!(errors, vec![errors::HandlingError::BatchTooLarge { size: max + 1, max }]);
+    })
+}
+
+#[test]
+fn should_answer_self_get_requests_from_the_hosts_own_state() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        System::set_block_number(5);
+        let host = Host::<Test>::default();
+        let dispatcher = Dispatcher::<Test>::default();
+        sp_io::storage::set(b"self_get", b"value");
+        let request = |dest, keys: Vec<Vec<u8>>| DispatchGet {
+            dest,
+            from: mocks::ismp::MODULE_ID.to_bytes(),
+            keys,
+            height: 0,
+            timeout_timestamp: 0,
+            gas_limit: 0,
+        };
+
+        // self GET requests can only be made through `dispatch_self_get`
+        assert!(dispatcher
+            .dispatch_request(DispatchRequest::Get(request(
+                host.host_state_machine(),
+                vec![b"self_get".to_vec()]
+            )))
+            .is_err());
+        let err = dispatcher
+            .dispatch_self_get(request(StateMachine::Kusama(2000), vec![b"self_get".to_vec()]))
+            .unwrap_err();
+        assert_eq!(err.kind, primitives::DispatchErrorKind::DestinationNotHost);
+        let too_many = (0..33u8).map(|i| vec![i]).collect();
+        let err =
+            dispatcher.dispatch_self_get(request(host.host_state_machine(), too_many)).unwrap_err();
+        assert_eq!(err.kind, primitives::DispatchErrorKind::ResponseTooLarge);
+
+        let nonce = Nonce::<Test>::get();
+        let keys = vec![b"self_get".to_vec(), b"missing".to_vec()];
+        dispatcher.dispatch_self_get(request(host.host_state_machine(), keys.clone())).unwrap();
+
+        let get = Request::Get(ismp_rs::router::Get {
+            source: host.host_state_machine(),
+            dest: host.host_state_machine(),
+            nonce,
+            from: mocks::ismp::MODULE_ID.to_bytes(),
+            keys,
+            height: 5,
+            timeout_timestamp: 0,
+            gas_limit: 0,
+        });
+        let values: BTreeMap<Vec<u8>, Option<Vec<u8>>> =
+            frame_support::storage::unhashed::get(&mocks::ismp::get_response_key(&get)).unwrap();
+        assert_eq!(
+            values,
+            BTreeMap::from([
+                (b"self_get".to_vec(), Some(b"value".to_vec())),
+                (b"missing".to_vec(), None)
+            ])
+        );
+        // nothing is committed for relayers
+        assert_eq!(RequestCommitments::<Test>::iter().count(), 0);
+    })
+}
+
+#[test]
+fn should_derive_keys_of_the_hosts_own_storage() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let pallet = <Ismp as frame_support::traits::PalletInfoAccess>::name();
+
+        let key = primitives::storage_map_key::<frame_support::Blake2_128Concat, _>(
+            pallet,
+            "StateCommitments",
+            &height,
+        );
+        assert_eq!(key, StateCommitments::<Test>::hashed_key_for(&height));
+        let value = sp_io::storage::get(&key).unwrap();
+        assert_eq!(
+            StateCommitment::decode(&mut &value[..]).ok(),
+            StateCommitments::<Test>::get(&height)
+        );
+
+        let key = primitives::storage_value_key(pallet, "RootHash");
+        assert_eq!(key, RootHash::<Test>::hashed_key().to_vec());
+
+        // modules read the host's own storage through a self GET
+        let key = primitives::storage_map_key::<frame_support::Blake2_128Concat, _>(
+            pallet,
+            "StateCommitments",
+            &height,
+        );
+        let nonce = Nonce::<Test>::get();
+        Dispatcher::<Test>::default()
+            .dispatch_self_get(DispatchGet {
+                dest: host.host_state_machine(),
+                from: mocks::ismp::MODULE_ID.to_bytes(),
+                keys: vec![key.clone()],
+                height: 0,
+                timeout_timestamp: 0,
+                gas_limit: 0,
+            })
+            .unwrap();
+        let get = Request::Get(ismp_rs::router::Get {
+            source: host.host_state_machine(),
+            dest: host.host_state_machine(),
+            nonce,
+            from: mocks::ismp::MODULE_ID.to_bytes(),
+            keys: vec![key.clone()],
+            height: System::block_number(),
+            timeout_timestamp: 0,
+            gas_limit: 0,
+        });
+        let values: BTreeMap<Vec<u8>, Option<Vec<u8>>> =
+            frame_support::storage::unhashed::get(&mocks::ismp::get_response_key(&get)).unwrap();
+        let value = values.get(&key).cloned().flatten().unwrap();
+        assert_eq!(
+            StateCommitment::decode(&mut &value[..]).ok(),
+            StateCommitments::<Test>::get(&height)
+        );
+    })
+}
+
+#[test]
+fn should_charge_modules_for_the_gas_they_report() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        let host = Host::<Test>::default();
+        let height = setup_mock_client::<_, Test>(&host);
+        let request = |nonce| Post {
+            source: height.id.state_id,
+            dest: host.host_state_machine(),
+            nonce,
+            from: vec![0u8; 32],
+            to: METERED_MODULE_ID.to_bytes(),
+            timeout_timestamp: 0,
+            data: vec![],
+            gas_limit: 0,
+        };
+        let messages = vec![Message::Request(RequestMessage {
+            requests: vec![request(0), request(1)],
+            proof: Proof { height: height.clone(), proof: vec![] },
+        })];
+        let declared = weight_info::get_weight::<Test>(&messages);
+        assert!(declared.all_gte(CALLBACK_WEIGHT_LIMIT * 2));
+
+        let info = Pallet::<Test>::handle_messages(messages).unwrap();
+        assert_eq!(ModuleGasUsed::<Test>::get(), CALLBACK_WEIGHT_USED * 2);
+        assert_eq!(
+            info.actual_weight,
+            Some(declared - CALLBACK_WEIGHT_LIMIT * 2 + CALLBACK_WEIGHT_USED * 2)
+        );
+    })
+}
+
+#[test]
+fn should_reject_messages_for_unknown_consensus_clients() {
+    let mut ext = new_test_ext();
+
+    ext.execute_with(|| {
+        frame_system::Pallet::<Test>::set_block_number(1);
+        let host = Host::<Test>::default();
+        let mut height = setup_mock_client::<_, Test>(&host);
+        height.id.consensus_state_id = *b"none";
+        let message = Message::Request(RequestMessage {
+            requests: vec![],
+            proof: Proof { height, proof: vec![] },
+        });
+
+        Pallet::<Test>::handle_messages(vec![message]).unwrap();
+        frame_system::Pallet::<Test>::assert_last_event(RuntimeEvent::Ismp(
+            Event::HandlingErrors {
+                errors: vec![HandlingError::UnknownConsensusClient { id: *b"none" }],
+            },
+        ));
+    })
+}
@@ -17,9 +17,15 @@
 //! This module provides a guide on how to provide static weights for consensus clients and module
 //! callbacks
 
-use crate::{primitives::ModuleId, Config};
+use crate::{
+    primitives::{ModuleId, PostEnvelope},
+    Config,
+};
 use alloc::boxed::Box;
-use frame_support::weights::Weight;
+use codec::Decode;
+use core::marker::PhantomData;
+use frame_support::{traits::Get, weights::Weight};
+use ismp_primitives::MembershipProof;
 use ismp_rs::{
     consensus::{ConsensusClientId, StateMachineId},
     messaging::{
@@ -125,6 +131,38 @@ impl WeightProvider for () {
     }
 }
 
+/// Conservatively estimates every module callback as two storage reads and two writes
+pub struct DefaultModuleWeight<T>(PhantomData<T>);
+
+impl<T: Config> IsmpModuleWeight for DefaultModuleWeight<T> {
+    fn on_accept(&self, _request: &Post) -> Weight {
+        T::DbWeight::get().reads_writes(2, 2)
+    }
+
+    fn on_timeout(&self, _request: &Request) -> Weight {
+        T::DbWeight::get().reads_writes(2, 2)
+    }
+
+    fn on_response(&self, _response: &Response) -> Weight {
+        T::DbWeight::get().reads_writes(2, 2)
+    }
+}
+
+/// A [`WeightProvider`] for runtimes that haven't benchmarked their modules, it uses
+/// [`DefaultModuleWeight`] for every module and provides no consensus client weights.
+/// Runtimes with benchmarked modules can wrap it, falling back to it for the other modules.
+pub struct DefaultWeightProvider<T>(PhantomData<T>);
+
+impl<T: Config> WeightProvider for DefaultWeightProvider<T> {
+    fn consensus_client(_id: ConsensusClientId) -> Option<Box<dyn ConsensusClientWeight>> {
+        None
+    }
+
+    fn module_callback(_dest_module: ModuleId) -> Option<Box<dyn IsmpModuleWeight>> {
+        Some(Box::new(DefaultModuleWeight::<T>(PhantomData)))
+    }
+}
+
 /// These functions account for storage reads and writes in the ismp message handlers
 /// They do not take into account proof verification, that is delegated to the Consensus client
 /// weight provider
@@ -147,6 +185,12 @@ pub trait WeightInfo {
     fn dispatch_get_request() -> Weight;
     /// Returns the weight consumed in dispatching a response
     fn dispatch_response() -> Weight;
+    /// Returns the weight consumed in verifying an mmr membership proof of `n` leaves in an mmr
+    /// of `2^h` leaves. It's charged for membership proofs of consensus clients without a
+    /// weight provider.
+    fn verify_membership(_n: u32, _h: u32) -> Weight {
+        Weight::zero()
+    }
 }
 
 impl WeightInfo for () {
@@ -187,6 +231,29 @@ impl WeightInfo for () {
     }
 }
 
+/// Returns the weight of verifying a membership proof of `items` values, as declared by the
+/// weight provider of the consensus client. Consensus clients without one are charged the
+/// benchmarked weight of verifying the mmr proof it decodes to.
+fn membership_weight<T: Config>(
+    state_machine: StateMachineId,
+    items: usize,
+    proof: &Proof,
+) -> Weight {
+    if let Some(handler) =
+        <T as Config>::WeightProvider::consensus_client(state_machine.consensus_state_id)
+    {
+        return handler.verify_membership(state_machine, items, proof)
+    }
+
+    match MembershipProof::decode(&mut &proof.proof[..]) {
+        Ok(proof) => {
+            let height = (u64::BITS - proof.mmr_size.leading_zeros()).saturating_sub(1);
+            <T as Config>::WeightInfo::verify_membership(proof.leaf_indices.len() as u32, height)
+        }
+        Err(_) => Weight::zero(),
+    }
+}
+
 /// Returns the weight that would be consumed when executing a batch of messages
 pub fn get_weight<T: Config>(messages: &[Message]) -> Weight {
     messages.into_iter().fold(Weight::zero(), |acc, msg| match msg {
@@ -199,6 +266,10 @@ pub fn get_weight<T: Config>(messages: &[Message]) -> Weight {
         Message::Request(msg) => {
             let state_machine = msg.proof.height.id;
             let cb_weight = msg.requests.iter().fold(Weight::zero(), |acc, req| {
+                // Enveloped requests are charged for the module they're delivered to
+                let req = PostEnvelope::open(req.clone())
+                    .map(|(post, _)| post)
+                    .unwrap_or_else(|_| req.clone());
                 let dest_module = ModuleId::from_bytes(req.to.as_slice()).ok();
                 let handle = dest_module
                     .map(|id| <T as Config>::WeightProvider::module_callback(id))
@@ -207,13 +278,8 @@ pub fn get_weight<T: Config>(messages: &[Message]) -> Weight {
                 acc + handle.on_accept(&req)
             });
 
-            let consensus_handler = <T as Config>::WeightProvider::consensus_client(
-                msg.proof.height.id.consensus_state_id,
-            )
-            .unwrap_or(Box::new(()));
-
             let proof_verification_weight =
-                consensus_handler.verify_membership(state_machine, msg.requests.len(), &msg.proof);
+                membership_weight::<T>(state_machine, msg.requests.len(), &msg.proof);
 
             acc + cb_weight +
                 proof_verification_weight +
@@ -237,13 +303,8 @@ pub fn get_weight<T: Config>(messages: &[Message]) -> Weight {
                     acc + handle.on_response(&res)
                 });
 
-                let consensus_handler = <T as Config>::WeightProvider::consensus_client(
-                    proof.height.id.consensus_state_id,
-                )
-                .unwrap_or(Box::new(()));
-
                 let proof_verification_weight =
-                    consensus_handler.verify_membership(state_machine, responses.len(), &proof);
+                    membership_weight::<T>(state_machine, responses.len(), &proof);
 
                 acc + cb_weight +
                     proof_verification_weight +
@@ -271,8 +332,14 @@ pub fn get_weight<T: Config>(messages: &[Message]) -> Weight {
                 )
                 .unwrap_or(Box::new(()));
 
-                let proof_verification_weight =
-                    consensus_handler.verify_state_proof(state_machine, requests.len(), &proof);
+                // The proof is verified once for the keys of each request
+                let proof_verification_weight = requests.iter().fold(Weight::zero(), |acc, req| {
+                    let keys = match req {
+                        Request::Get(ref get) => get.keys.len(),
+                        _ => return acc,
+                    };
+                    acc + consensus_handler.verify_state_proof(state_machine, keys, &proof)
+                });
 
                 acc + cb_weight +
                     proof_verification_weight +
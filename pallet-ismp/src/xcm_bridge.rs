@@ -0,0 +1,154 @@
+// Copyright (C) 2023 Polytope Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Passing of XCM messages over ISMP.
+//! [`XcmIsmpBridge`] sends XCM messages to parachains as post requests addressed to
+//! [`XCM_MODULE_ID`], and [`XcmIsmpModule`] executes the XCM messages carried by incoming post
+//! requests with the sending parachain as origin.
+
+use crate::{dispatcher::Dispatcher, host::Host, Config};
+use alloc::{format, string::ToString};
+use codec::{DecodeLimit, Encode};
+use core::marker::PhantomData;
+use frame_support::{traits::Get, weights::Weight, PalletId};
+use ismp_rs::{
+    error::Error as IsmpError,
+    host::{IsmpHost, StateMachine},
+    module::IsmpModule,
+    router::{DispatchPost, DispatchRequest, IsmpDispatcher, Post, Request, Response},
+};
+use xcm::{latest::prelude::*, VersionedXcm, MAX_XCM_DECODE_DEPTH};
+
+/// Module id of the XCM bridge, XCM messages are sent from and delivered to this module
+pub const XCM_MODULE_ID: PalletId = PalletId(*b"ismp-xcm");
+
+/// Returns the state machine of the sibling parachain at `location`
+fn state_machine_of<T: Config>(location: &MultiLocation) -> Option<StateMachine> {
+    let id = match (location.parents, location.interior) {
+        (1, X1(Parachain(id))) => id,
+        _ => return None,
+    };
+
+    match Host::<T>::default().host_state_machine() {
+        StateMachine::Kusama(_) => Some(StateMachine::Kusama(id)),
+        StateMachine::Polkadot(_) => Some(StateMachine::Polkadot(id)),
+        _ => None,
+    }
+}
+
+/// Returns the location of a parachain state machine relative to a sibling parachain
+fn location_of(state_machine: StateMachine) -> Option<MultiLocation> {
+    match state_machine {
+        StateMachine::Kusama(id) | StateMachine::Polkadot(id) => {
+            Some(MultiLocation::new(1, X1(Parachain(id))))
+        }
+        _ => None,
+    }
+}
+
+/// Sends XCM messages to sibling parachains as post requests. Destinations that aren't sibling
+/// parachains are left to the next router.
+pub struct XcmIsmpBridge<T>(PhantomData<T>);
+
+impl<T: Config> SendXcm for XcmIsmpBridge<T> {
+    type Ticket = DispatchPost;
+
+    fn validate(
+        destination: &mut Option<MultiLocation>,
+        message: &mut Option<Xcm<()>>,
+    ) -> SendResult<DispatchPost> {
+        let location = destination.as_ref().ok_or(SendError::MissingArgument)?;
+        let dest = state_machine_of::<T>(location).ok_or(SendError::NotApplicable)?;
+        let message = message.take().ok_or(SendError::MissingArgument)?;
+        destination.take();
+
+        let post = DispatchPost {
+            dest,
+            from: XCM_MODULE_ID.0.to_vec(),
+            to: XCM_MODULE_ID.0.to_vec(),
+            timeout_timestamp: 0,
+            data: VersionedXcm::<()>::from(message).encode(),
+            gas_limit: 0,
+        };
+
+        Ok((post, MultiAssets::new()))
+    }
+
+    fn deliver(post: DispatchPost) -> Result<XcmHash, SendError> {
+        let hash = sp_io::hashing::blake2_256(&post.data);
+        Dispatcher::<T>::default()
+            .dispatch_request(DispatchRequest::Post(post))
+            .map_err(|_| SendError::Transport("Failed to dispatch ISMP request"))?;
+        Ok(hash)
+    }
+}
+
+/// Executes the XCM messages carried by post requests from the [`XcmIsmpBridge`]s of sibling
+/// parachains, spending at most `MaxWeight` on each message
+pub struct XcmIsmpModule<Executor, Call, MaxWeight>(PhantomData<(Executor, Call, MaxWeight)>);
+
+impl<Executor, Call, MaxWeight> Default for XcmIsmpModule<Executor, Call, MaxWeight> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<Executor, Call, MaxWeight> IsmpModule for XcmIsmpModule<Executor, Call, MaxWeight>
+where
+    Executor: ExecuteXcm<Call>,
+    Call: codec::Decode,
+    MaxWeight: Get<Weight>,
+{
+    fn on_accept(&self, request: Post) -> Result<(), IsmpError> {
+        // Only the XCM bridge of the source parachain may execute messages with its origin
+        if request.from != XCM_MODULE_ID.0.to_vec() {
+            Err(IsmpError::ImplementationSpecific(
+                "XCM messages must be sent by the XCM bridge".to_string(),
+            ))?
+        }
+        let origin = location_of(request.source).ok_or_else(|| {
+            IsmpError::ImplementationSpecific(format!(
+                "XCM from unsupported state machine {:?}",
+                request.source
+            ))
+        })?;
+        let message = VersionedXcm::<Call>::decode_all_with_depth_limit(
+            MAX_XCM_DECODE_DEPTH,
+            &mut &*request.data,
+        )
+        .ok()
+        .and_then(|message| Xcm::<Call>::try_from(message).ok())
+        .ok_or_else(|| {
+            IsmpError::ImplementationSpecific("Failed to decode XCM message".to_string())
+        })?;
+
+        let hash = sp_io::hashing::blake2_256(&request.data);
+        match Executor::execute_xcm(origin, message, hash, MaxWeight::get()) {
+            Outcome::Complete(_) => Ok(()),
+            outcome => Err(IsmpError::ImplementationSpecific(format!(
+                "Failed to execute XCM message: {outcome:?}"
+            ))),
+        }
+    }
+
+    fn on_response(&self, _response: Response) -> Result<(), IsmpError> {
+        Err(IsmpError::ImplementationSpecific("XCM messages have no responses".to_string()))
+    }
+
+    fn on_timeout(&self, _request: Request) -> Result<(), IsmpError> {
+        // XCM messages are sent without a timeout
+        Ok(())
+    }
+}